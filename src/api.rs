@@ -1,242 +1,700 @@
 use crate::app::App;
+use crate::cache::Cache;
+use crate::groupme::{
+    Attachment, Chat, Conversation, Group, GroupMeClient, GroupUpdate, Member, Message,
+    MessageEvent, MessageQuery, NewMember, NewMessage,
+};
 use crate::lists::{DirectMessage, GroupInfo, MsgInfo};
+use crate::utils::config::Config;
 
-use serde_json::Value;
-
-use ureq;
-
-use std::error::Error;
+use crate::error::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 
 use textwrap::{fill, Options};
 
-use tui::style::{Color, Style};
-use tui::text::Text;
-
 use chrono::prelude::*;
 
-// Naive way to get rid of quotes
-fn clean_str(input: String) -> String {
-    let mut cleaned = input.clone();
-    cleaned.pop();
-    cleaned.remove(0);
-    return cleaned;
-}
+use tui::text::Text;
+
+// Messages fetched per request, and shown at once before scrolling back
+const PAGE_SIZE: u32 = 100;
 
-pub fn get_userid(secret: &str) -> Result<String, Box<dyn Error>> {
-    let url = format!("https://api.groupme.com/v3/users/me?token={}", secret);
-    let body: String = ureq::get(&url).call()?.into_string()?;
-    let resp: Value = serde_json::from_str(&body)?;
-    Ok(clean_str(resp["response"]["id"].to_string()))
+pub fn get_userid(client: &GroupMeClient, cache: &Cache) -> Result<String> {
+    match client.me() {
+        Ok(user) => {
+            let _ = cache.save_user(&user);
+            Ok(user.id)
+        }
+        Err(e) => cache.user().map(|user| user.id).ok_or_else(|| e.into()),
+    }
 }
 
 /* Returns vector of GroupInfo to create a Lists object
  * of Groups
- * secret: GroupMe secret api key
+ * client: GroupMe api client
  */
-pub fn get_groups(secret: String) -> Result<Vec<GroupInfo>, Box<dyn Error>> {
-    let mut groups = Vec::new();
-
-    let url = format!(
-        "https://api.groupme.com/v3/groups?token={}&omit=membership&per_page=100",
-        secret
-    );
-    let body: String = ureq::get(&url).call()?.into_string()?;
+pub fn get_groups(client: &GroupMeClient, cache: &Cache) -> Result<Vec<GroupInfo>> {
+    let groups = match client.groups(1, 100) {
+        Ok(groups) => {
+            // Failing to cache only means the next start has to wait on the network
+            let _ = cache.save_groups(&groups);
+            groups
+        }
+        Err(e) => {
+            let cached = cache.groups();
+            if cached.is_empty() {
+                return Err(e.into());
+            }
+            cached
+        }
+    };
 
-    let resp: Value = serde_json::from_str(&body)?;
+    Ok(groups.into_iter().map(to_groupinfo).collect())
+}
 
-    let group_info = &resp["response"];
+/* Groups as they were last fetched, empty if they never have been */
+pub fn cached_groups(cache: &Cache) -> Vec<GroupInfo> {
+    cache.groups().into_iter().map(to_groupinfo).collect()
+}
 
-    for i in 0..group_info.as_array().unwrap().len() {
-        let name = clean_str(group_info[i]["name"].to_string());
-        let id = clean_str(group_info[i]["id"].to_string());
-        let lmid = clean_str(group_info[i]["messages"]["last_message_id"].to_string());
-        let message_count = group_info[i]["messages"]["count"].as_u64().unwrap();
+fn to_groupinfo(group: Group) -> GroupInfo {
+    GroupInfo {
+        name: group.name,
+        id: group.id,
+        lmid: group.messages.last_message_id.unwrap_or_default(),
+        message_count: group.messages.count,
+        unread: 0,
+        last_activity: group
+            .messages
+            .last_message_created_at
+            .or(group.updated_at)
+            .unwrap_or_default(),
+    }
+}
 
-        let group = GroupInfo {
-            name,
-            id,
-            lmid,
-            message_count,
-        };
+/* Create a new group with the user as its owner
+ * client: GroupMe api client
+ * name: Name of the group
+ */
+pub fn create_group(client: &GroupMeClient, name: &str) -> Result<GroupInfo> {
+    let group = client.create_group(&GroupUpdate {
+        name: Some(name.to_string()),
+        ..Default::default()
+    })?;
+    Ok(to_groupinfo(group))
+}
 
-        groups.push(group);
+/* Change a group's name, description and/or image, updating the cached copy to match
+ * client: GroupMe api client
+ * cache: Cache holding the group
+ * group_id: Group to update
+ * update: Fields to change
+ */
+pub fn update_group(
+    client: &GroupMeClient,
+    cache: &Cache,
+    group_id: &str,
+    update: &GroupUpdate,
+) -> Result<GroupInfo> {
+    let updated = client.update_group(group_id, update)?;
+    let mut cached = cache.groups();
+    if let Some(group) = cached.iter_mut().find(|g| g.id == group_id) {
+        *group = updated.clone();
+        let _ = cache.save_groups(&cached);
     }
+    Ok(to_groupinfo(updated))
+}
+
+pub fn destroy_group(client: &GroupMeClient, group_id: &str) -> Result<()> {
+    Ok(client.destroy_group(group_id)?)
+}
 
-    Ok(groups)
+/* Add users to a group. GroupMe adds them in the background, so they may take a moment to show
+ * up in the group's members
+ * client: GroupMe api client
+ * group_id: Group to add to
+ * members: Users to add, along with the nickname they will have in the group
+ */
+pub fn add_members(client: &GroupMeClient, group_id: &str, members: Vec<NewMember>) -> Result<()> {
+    client.add_members(group_id, &members)?;
+    Ok(())
+}
+
+/* Remove a member from a group, which leaves the group when it is the user's own membership
+ * client: GroupMe api client
+ * group_id: Group to remove from
+ * membership_id: Id of the membership, not of the user
+ */
+pub fn remove_member(client: &GroupMeClient, group_id: &str, membership_id: &str) -> Result<()> {
+    Ok(client.remove_member(group_id, membership_id)?)
+}
+
+/* Returns the members of a group
+ * client: GroupMe api client
+ * group_id: Group to look up
+ */
+pub fn get_members(client: &GroupMeClient, group_id: &str) -> Result<Vec<Member>> {
+    Ok(client.group(group_id)?.members)
 }
 
 /* Return vector of chats that can be used to create a List Object
- * app: Main application object
+ * client: GroupMe api client
  */
-pub fn get_chats(secret: String) -> Result<Vec<DirectMessage>, Box<dyn Error>> {
-    let mut direct_messages = Vec::new();
-    let url = format!(
-        "https://api.groupme.com/v3/chats?token={}&per_page=100",
-        secret
-    );
+pub fn get_chats(client: &GroupMeClient, cache: &Cache) -> Result<Vec<DirectMessage>> {
+    let chats = match client.chats(1, 100) {
+        Ok(chats) => {
+            let _ = cache.save_chats(&chats);
+            chats
+        }
+        Err(e) => {
+            let cached = cache.chats();
+            if cached.is_empty() {
+                return Err(e.into());
+            }
+            cached
+        }
+    };
 
-    let body: String = ureq::get(&url).call()?.into_string()?;
+    Ok(chats.into_iter().map(to_direct_message).collect())
+}
 
-    let resp: Value = serde_json::from_str(&body)?;
+/* Chats as they were last fetched, empty if they never have been */
+pub fn cached_chats(cache: &Cache) -> Vec<DirectMessage> {
+    cache.chats().into_iter().map(to_direct_message).collect()
+}
+
+fn to_direct_message(chat: Chat) -> DirectMessage {
+    DirectMessage {
+        name: chat.other_user.name,
+        id: chat.other_user.id,
+        lmid: chat
+            .last_message
+            .as_ref()
+            .map(|msg| msg.id.clone())
+            .unwrap_or_default(),
+        message_count: chat.messages_count,
+        unread: 0,
+        last_activity: chat
+            .last_message
+            .as_ref()
+            .map(|msg| msg.created_at)
+            .or(chat.updated_at)
+            .unwrap_or_default(),
+    }
+}
 
-    let dm_info = &resp["response"];
+/* Find a group or direct message by its id or name, ignoring case. Groups are checked first
+ * client: GroupMe api client
+ * cache: Used when GroupMe can't be reached
+ * query: Id or name to look for
+ */
+pub fn find_conversation(
+    client: &GroupMeClient,
+    cache: &Cache,
+    query: &str,
+) -> Result<(Conversation, String)> {
+    match find_group(client, cache, query) {
+        Ok(found) => Ok(found),
+        Err(_) => find_dm(client, cache, query)
+            .map_err(|_| format!("No group or direct message called {}", query).into()),
+    }
+}
 
-    for i in 0..dm_info.as_array().unwrap().len() {
-        let name = clean_str(dm_info[i]["other_user"]["name"].to_string());
-        let id = clean_str(dm_info[i]["other_user"]["id"].to_string());
+/* Find a group by its id or name, ignoring case */
+pub fn find_group(
+    client: &GroupMeClient,
+    cache: &Cache,
+    query: &str,
+) -> Result<(Conversation, String)> {
+    let lower = query.to_lowercase();
+    get_groups(client, cache)?
+        .into_iter()
+        .find(|g| g.id == query || g.name.to_lowercase() == lower)
+        .map(|group| (Conversation::Group(group.id), group.name))
+        .ok_or_else(|| format!("No group called {}", query).into())
+}
 
-        let dm = DirectMessage { name, id };
-        direct_messages.push(dm);
+/* Find a direct message by the other user's id or name, ignoring case */
+pub fn find_dm(
+    client: &GroupMeClient,
+    cache: &Cache,
+    query: &str,
+) -> Result<(Conversation, String)> {
+    let lower = query.to_lowercase();
+    get_chats(client, cache)?
+        .into_iter()
+        .find(|d| d.id == query || d.name.to_lowercase() == lower)
+        .map(|dm| (Conversation::Direct(dm.id), dm.name))
+        .ok_or_else(|| format!("No direct message with {}", query).into())
+}
+
+/* Latest messages of a conversation, oldest first
+ * client: GroupMe api client
+ * conv: Conversation to fetch from
+ * since_id: Only return messages newer than this one
+ * limit: Most messages to return, up to 100
+ */
+pub fn recent_messages(
+    client: &GroupMeClient,
+    conv: &Conversation,
+    since_id: Option<String>,
+    limit: u32,
+) -> Result<Vec<Message>> {
+    let query = MessageQuery {
+        since_id,
+        limit: Some(limit),
+        ..Default::default()
+    };
+    let mut msgs = client.messages(conv, &query)?;
+    msgs.sort_by_key(|msg| msg.created_at);
+    Ok(msgs)
+}
+
+/* Return vector of MsgInfo that can be used to create a List Object. Messages newer than the
+ * cached ones are fetched and cached, when that fails the cached messages are shown on their own
+ * app: Main application object
+ */
+pub fn get_messages(app: &mut App<'static>, dm: bool) -> Result<()> {
+    let conv = conversation(app, dm);
+    let mut msgs = app.cache.messages(&conv);
+
+    match fetch_new(&app.client, &conv, msgs.last()) {
+        Ok(new) => {
+            let _ = app.cache.append_messages(&conv, &new);
+            msgs.extend(new);
+        }
+        Err(e) if msgs.is_empty() => return Err(e),
+        Err(e) => app.show_error(format!("Offline, showing cached messages ({})", e)),
     }
 
-    Ok(direct_messages)
+    show_latest(app, msgs);
+    Ok(())
 }
 
-/* Return vector of MsgInfo that can be used to create a List Object
+/* Show the cached messages of a conversation without touching the network
  * app: Main application object
  */
-pub fn get_messages(app: &mut App<'static>, dm: bool) -> Result<(), Box<dyn Error>> {
-    let mut msgs = Vec::new();
-    let url = if dm {
-        format!(
-            "https://api.groupme.com/v3/direct_messages?other_user_id={}&token={}",
-            app.dm_id, app.secret
-        )
-    } else {
-        format!(
-            "https://api.groupme.com/v3/groups/{}/messages?token={}&limit=100",
-            app.group_id, app.secret
-        )
-    };
+pub fn get_cached_messages(app: &mut App<'static>, dm: bool) {
+    let msgs = app.cache.messages(&conversation(app, dm));
+    show_latest(app, msgs);
+}
 
-    let body: String = ureq::get(&url).call()?.into_string()?;
+// Only the latest page is shown at first, scrolling back loads the rest
+fn show_latest(app: &mut App<'static>, msgs: Vec<Message>) {
+    let start = msgs.len().saturating_sub(PAGE_SIZE as usize);
+    // Replies quote messages from the whole conversation, not just the page shown
+    let msgs = to_msginfos(app, &msgs[start..], &msgs);
+    app.messages.set_items(msgs);
+}
 
-    let resp: Value = serde_json::from_str(&body)?;
+/* Show the cached messages of a conversation from the given one up to the newest, so a search
+ * hit is in view without paging back to it. False if the message isn't cached
+ * app: Main application object
+ * msg_id: Message to start from
+ */
+pub fn show_from(app: &mut App<'static>, dm: bool, msg_id: &str) -> bool {
+    let msgs = app.cache.messages(&conversation(app, dm));
+    let start = match msgs.iter().position(|msg| msg.id == msg_id) {
+        Some(start) => start,
+        None => return false,
+    };
+    let msgs = to_msginfos(app, &msgs[start..], &msgs);
+    app.messages.set_items(msgs);
+    app.messages.state.select(Some(0));
+    true
+}
 
-    let msg_info = if dm {
-        &resp["response"]["direct_messages"]
-    } else {
-        &resp["response"]["messages"]
+/* Fetch every message newer than the newest cached one, oldest first. Without anything cached
+ * just the latest page is fetched
+ * client: GroupMe api client
+ * conv: Conversation to fetch from
+ * newest: Newest cached message
+ */
+fn fetch_new(
+    client: &GroupMeClient,
+    conv: &Conversation,
+    newest: Option<&Message>,
+) -> Result<Vec<Message>> {
+    let newest = match newest {
+        Some(newest) => newest,
+        None => {
+            let mut page = client.messages(conv, &page_query(None))?;
+            page.reverse();
+            return Ok(page);
+        }
     };
-    for i in (0..msg_info.as_array().unwrap().len()).rev() {
-        let name = clean_str(msg_info[i]["name"].to_string());
-        let mut text = if msg_info[i]["text"].is_null() {
-            "".to_string()
-        } else {
-            clean_str(msg_info[i]["text"].to_string())
-        };
-
-        let num_likes = msg_info[i]["favorited_by"].as_array().unwrap().len();
-        let mut liked = "♡";
-        for like in msg_info[i]["favorited_by"].as_array().unwrap() {
-            if clean_str(like.to_string()) == app.user_id {
-                liked = "♥";
+
+    let mut new = Vec::new();
+    match conv {
+        Conversation::Group(_) => {
+            let mut after_id = newest.id.clone();
+            loop {
+                let query = MessageQuery {
+                    after_id: Some(after_id.clone()),
+                    limit: Some(PAGE_SIZE),
+                    ..Default::default()
+                };
+                let page = client.messages(conv, &query)?;
+                let full = page.len() == PAGE_SIZE as usize;
+                new.extend(page);
+                new.sort_by_key(|msg| msg.created_at);
+                match new.last() {
+                    Some(last) if full => after_id = last.id.clone(),
+                    _ => break,
+                }
             }
         }
-        let id = clean_str(msg_info[i]["id"].to_string());
-
-        let mut attachments = Vec::new();
-        if !msg_info[i]["attachments"].is_null() {
-            for j in 0..msg_info[i]["attachments"].as_array().unwrap().len() {
-                if msg_info[i]["attachments"][j]["type"] == "image" {
-                    let attachment = clean_str(msg_info[i]["attachments"][j]["url"].to_string());
-                    attachments.push(attachment);
+        // Direct messages can't be paged forwards, so page back until the cached ones are reached
+        Conversation::Direct(_) => {
+            let mut before_id = None;
+            loop {
+                let page = client.messages(conv, &page_query(before_id))?;
+                before_id = page.last().map(|msg| msg.id.clone());
+                // Pages are newest first, so everything from the newest cached message on is
+                // cached already. Timestamps are to the second, so they only decide it if that
+                // message is gone
+                let mut reached_cache = false;
+                for msg in page {
+                    if msg.id == newest.id || msg.created_at < newest.created_at {
+                        reached_cache = true;
+                        break;
+                    }
+                    new.push(msg);
+                }
+                if reached_cache || before_id.is_none() {
+                    break;
                 }
             }
+            new.reverse();
         }
+    }
+    Ok(new)
+}
 
-        let indentation = " ".repeat(2);
-        let option = Options::new(app.t_width.into())
-            .initial_indent(&indentation)
-            .subsequent_indent(&indentation);
-
-        let mut disp = Text::styled(
-            format!("{} - {} {}", name.clone(), num_likes, liked),
-            Style::default().fg(Color::Blue),
-        );
-        text = text.replace("\\n", "\n");
-        text = text.replace("\\\"", "\"");
-        if !text.is_empty() {
-            text = fill(&text, &option);
-            disp.extend(Text::raw(text.clone()));
+/* Load the page of messages before the oldest one currently loaded and put them in front of
+ * App.messages, returning how many were added (0 once the start of the conversation is reached).
+ * Cached messages are used first, then older ones are fetched and cached
+ * app: Main application object
+ */
+pub fn get_older_messages(app: &mut App<'static>, dm: bool) -> Result<usize> {
+    let before_id = match app.messages.items.first() {
+        Some(msg) => msg.id.clone(),
+        None => return Ok(0),
+    };
+    let conv = conversation(app, dm);
+
+    let cached = app.cache.messages(&conv);
+    let older = match cached.iter().position(|msg| msg.id == before_id) {
+        Some(i) if i > 0 => cached[i.saturating_sub(PAGE_SIZE as usize)..i].to_vec(),
+        _ => {
+            let mut page = app.client.messages(&conv, &page_query(Some(before_id)))?;
+            let _ = app.cache.append_messages(&conv, &page);
+            // API returns newest first, but they are displayed oldest first
+            page.reverse();
+            page
         }
-        for j in 0..attachments.len() {
-            disp.extend(Text::from(indentation.clone() + &attachments[j]));
+    };
+
+    let msgs = to_msginfos(app, &older, cached.iter().chain(&older));
+    let added = msgs.len();
+    app.messages.prepend_items(msgs);
+
+    Ok(added)
+}
+
+fn conversation(app: &App<'static>, dm: bool) -> Conversation {
+    if dm {
+        Conversation::Direct(app.dm_id.clone())
+    } else {
+        Conversation::Group(app.group_id.clone())
+    }
+}
+
+fn page_query(before_id: Option<String>) -> MessageQuery {
+    MessageQuery {
+        before_id,
+        limit: Some(PAGE_SIZE),
+        ..Default::default()
+    }
+}
+
+/* Render messages for the messages list, quoting the messages they reply to
+ * app: Main application object
+ * msgs: Messages to render
+ * known: Messages to look for the ones replied to in
+ */
+fn to_msginfos<'a>(
+    app: &App<'static>,
+    msgs: &[Message],
+    known: impl IntoIterator<Item = &'a Message>,
+) -> Vec<MsgInfo<'static>> {
+    let by_id: HashMap<&str, &Message> = known.into_iter().map(|m| (m.id.as_str(), m)).collect();
+    msgs.iter()
+        .map(|msg| {
+            let quote = msg
+                .reply_id()
+                .and_then(|id| by_id.get(id))
+                .map(|parent| quote_of(parent));
+            to_msginfo(msg, quote, &app.user_id, app.t_width, &app.config)
+        })
+        .collect()
+}
+
+// Short excerpt of a message to quote above replies to it
+pub fn quote_of(parent: &Message) -> String {
+    let text = parent.text.as_deref().unwrap_or_default().trim_end();
+    let first_line = text.lines().next().unwrap_or("");
+    let mut excerpt: String = first_line.chars().take(60).collect();
+    // Cut short if the first line was too long, or there are lines after it
+    if excerpt != text {
+        excerpt.push('…');
+    }
+    format!("{}: {}", parent.name, excerpt)
+}
+
+/* Render a message for the messages list
+ * msg: Message to render
+ * quote: Excerpt of the message it replies to, from quote_of
+ * user_id: User's id, to show likes and mentions of the user
+ * t_width: Width to wrap text to
+ * config: User's configuration, for colours and the timestamp format
+ */
+pub fn to_msginfo(
+    msg: &Message,
+    quote: Option<String>,
+    user_id: &str,
+    t_width: u16,
+    config: &Config,
+) -> MsgInfo<'static> {
+    let num_likes = msg.favorited_by.len();
+    let liked = msg.liked_by(user_id);
+
+    let mut reply = None;
+    let mut text = msg.text.clone().unwrap_or_default();
+    for attachment in &msg.attachments {
+        match attachment {
+            Attachment::Reply {
+                reply_id,
+                base_reply_id,
+            } => reply = Some((reply_id.clone(), base_reply_id.clone())),
+            Attachment::Emoji { placeholder, .. } if !placeholder.is_empty() => {
+                text = text.replace(placeholder.as_str(), "[emoji]");
+            }
+            _ => {}
         }
+    }
 
-        let message = MsgInfo {
-            id,
+    let indentation = " ".repeat(2);
+    let option = Options::new(t_width.into())
+        .initial_indent(&indentation)
+        .subsequent_indent(&indentation);
+
+    let mentioned = msg.attachments.iter().any(|attachment| match attachment {
+        Attachment::Mentions { user_ids, .. } => user_ids.iter().any(|id| id == user_id),
+        _ => false,
+    });
+    let header_style = if mentioned {
+        config.theme.mention
+    } else {
+        config.theme.name
+    };
+    let sent = Local
+        .timestamp(msg.created_at, 0)
+        .format(&config.timestamp_format);
+    let mut disp = Text::styled(
+        format!(
+            "{} - {} - {} {}{}",
+            msg.name,
+            sent,
             num_likes,
-            display: disp.clone(),
-            attachments,
-            liked: liked == "♥",
-        };
+            if liked { "♥" } else { "♡" },
+            if mentioned { " @you" } else { "" }
+        ),
+        header_style,
+    );
+    if !text.is_empty() {
+        disp.extend(Text::raw(fill(&text, &option)));
+    }
+    for attachment in &msg.attachments {
+        if let Some(line) = attachment_line(attachment, msg.event.as_ref()) {
+            disp.extend(Text::styled(fill(&line, &option), config.theme.attachment));
+        }
+    }
 
-        msgs.push(message);
-        //msgs.push(message);
+    MsgInfo {
+        message: msg.clone(),
+        id: msg.id.clone(),
+        name: msg.name.clone(),
+        text,
+        num_likes,
+        display: disp,
+        attachments: msg.attachments.clone(),
+        liked,
+        reply_to: reply.as_ref().map(|(id, _)| id.clone()),
+        base_reply_id: reply.map(|(_, base)| base),
+        quote,
     }
+}
 
-    app.messages.set_items(msgs);
+/* Line describing an attachment below the message text. Replies, mentions and emoji are shown
+ * as part of the text instead, so they have no line of their own
+ * attachment: Attachment to describe
+ * event: Event of the message, which holds the details of polls and calendar events
+ */
+pub fn attachment_line(attachment: &Attachment, event: Option<&MessageEvent>) -> Option<String> {
+    let data = event.map(|e| &e.data);
+    match attachment {
+        Attachment::Image { url } | Attachment::LinkedImage { url } => Some(url.clone()),
+        Attachment::Video { url, .. } => Some(format!("Video: {}", url)),
+        Attachment::File {
+            file_id,
+            name,
+            size,
+        } => Some(match (name, size) {
+            (Some(name), Some(size)) => format!("File: {} ({})", name, file_size(*size)),
+            (Some(name), None) => format!("File: {}", name),
+            _ => format!("File: {}", file_id),
+        }),
+        Attachment::Location { name, lat, lng } => {
+            Some(format!("Location: {} ({}, {})", name, lat, lng))
+        }
+        Attachment::Poll { poll_id } => {
+            let subject = data.and_then(|d| d["poll"]["subject"].as_str());
+            Some(match subject {
+                Some(subject) => format!("Poll: {}", subject),
+                None => format!("Poll: {}", poll_id),
+            })
+        }
+        Attachment::Event { event_id, .. } => {
+            let name = data.and_then(|d| d["event"]["name"].as_str());
+            let start = data
+                .and_then(|d| d["event"]["start_at"].as_str())
+                .and_then(|start| DateTime::parse_from_rfc3339(start).ok())
+                .map(|start| {
+                    start
+                        .with_timezone(&Local)
+                        .format("%a %b %e %H:%M")
+                        .to_string()
+                });
+            Some(match (name, start) {
+                (Some(name), Some(start)) => format!("Event: {} - {}", name, start),
+                (Some(name), None) => format!("Event: {}", name),
+                _ => format!("Event: {}", event_id),
+            })
+        }
+        Attachment::Split { .. } => Some("Split: bill split request".to_string()),
+        Attachment::Unknown => Some("[unsupported attachment]".to_string()),
+        Attachment::Emoji { .. } | Attachment::Mentions { .. } | Attachment::Reply { .. } => None,
+    }
+}
 
-    Ok(())
+// Human readable file size, e.g. 1.5 MB
+fn file_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
 }
 
 pub fn send_message(
-    secret: String,
+    client: &GroupMeClient,
     id: String,
     message: String,
+    attachments: Vec<Attachment>,
     dm: bool,
-) -> Result<(), Box<dyn Error>> {
-    let url = if dm {
-        format!(
-            "https://api.groupme.com/v3/direct_messages?token={}&other_user_id={}",
-            secret, id
-        )
+) -> Result<Message> {
+    let conv = if dm {
+        Conversation::Direct(id)
     } else {
-        format!(
-            "https://api.groupme.com/v3/groups/{}/messages?token={}",
-            id, secret
-        )
+        Conversation::Group(id)
     };
+    let mut new_message = NewMessage::new(&message);
+    new_message.attachments = attachments;
+    Ok(client.send_message(&conv, &new_message)?)
+}
 
-    let guid = Local::now().timestamp().to_string();
-    let msg_json = if dm {
-        ureq::json!({
-            "direct_message": {
-                "source_guid": &guid,
-                "recipient_id": id,
-                "text": message,
-                "attachments": []
-            }
-        })
+/* Like or unlike the selected message, updating it in place rather than refetching
+ * app: Main application object
+ */
+pub fn like_message(app: &mut App<'static>, dm: bool) -> Result<()> {
+    let selected = app.messages.state.selected().ok_or("No message selected")?;
+    let msg = &app.messages.items[selected];
+    let id = if dm { &app.dm_id } else { &app.group_id };
+    let mut updated = msg.message.clone();
+    if msg.liked {
+        app.client.unlike(id, &msg.id)?;
+        updated.favorited_by.retain(|user| user != &app.user_id);
     } else {
-        ureq::json!({
-            "message": {
-                "source_guid": &guid,
-                "text": message,
-                "attachments": []
-            }
-        })
-    };
-    ureq::post(&url).send_json(msg_json)?;
+        app.client.like(id, &msg.id)?;
+        updated.favorited_by.push(app.user_id.clone());
+    }
+
+    let _ = app
+        .cache
+        .append_messages(&conversation(app, dm), &[updated.clone()]);
+    let quote = app.messages.items[selected].quote.clone();
+    app.messages.items[selected] =
+        to_msginfo(&updated, quote, &app.user_id, app.t_width, &app.config);
     Ok(())
 }
 
-pub fn like_message(app: &mut App<'static>, dm: bool) -> Result<(), Box<dyn Error>> {
-    let msg = &app.messages.items[app.messages.state.selected().unwrap()];
-    let id = if dm { &app.dm_id } else { &app.group_id };
-    if msg.liked == true {
-        let url = format!(
-            "https://api.groupme.com/v3/messages/{}/{}/unlike?token={}",
-            id, msg.id, app.secret
+/* Upload a local image file, returning the url to attach to a message
+ * client: GroupMe api client
+ * path: Image to upload
+ */
+pub fn upload_image(client: &GroupMeClient, path: &Path) -> Result<String> {
+    let content_type = image_type(path).ok_or("Not a supported image type")?;
+    let image = fs::read(path)?;
+    Ok(client.upload_image(&image, content_type)?)
+}
+
+// Mime type of an image, based on its extension
+pub fn image_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_ref() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "bmp" => Some("image/bmp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> Message {
+        serde_json::from_value(serde_json::json!({"id": "1", "name": "Ann", "text": text})).unwrap()
+    }
+
+    #[test]
+    fn quote_keeps_short_messages_whole() {
+        assert_eq!(quote_of(&message("héllo wörld ✓")), "Ann: héllo wörld ✓");
+        assert_eq!(
+            quote_of(&message("trailing newline\n")),
+            "Ann: trailing newline"
         );
-        ureq::post(&url).call()?;
-    } else {
-        let url = format!(
-            "https://api.groupme.com/v3/messages/{}/{}/like?token={}",
-            id, msg.id, app.secret
+    }
+
+    #[test]
+    fn quote_cuts_long_and_multiline_messages() {
+        let long = "é".repeat(61);
+        assert_eq!(
+            quote_of(&message(&long)),
+            format!("Ann: {}…", "é".repeat(60))
         );
-        ureq::post(&url).call()?;
+        assert_eq!(quote_of(&message("first\nsecond")), "Ann: first…");
     }
-    Ok(())
 }
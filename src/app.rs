@@ -1,20 +1,80 @@
 use crate::api::*;
+use crate::cache::Cache;
+use crate::commands::run_command;
+use crate::composer::Composer;
+use crate::error::Result;
+use crate::groupme::{self, Attachment, Conversation, GroupMeClient, Member};
+use crate::listener::{ConnectionState, ListenerCommand, PushEvent};
 use crate::lists::*;
+use crate::mentions::{build_mentions, mention_query, suggestions};
+use crate::search::{search, Query, SearchHit};
+use crate::utils::config::{Config, LayoutConfig};
+use crate::utils::layout_state;
+use crate::utils::read_state::ReadState;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::slice;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// Least time between telling others the user is typing
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+
+// How long someone is shown as typing after their last typing event
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct App<'a> {
     pub groups: Lists<GroupInfo>,
     pub dms: Lists<DirectMessage>,
     pub messages: Lists<MsgInfo<'a>>,
+    pub config: Config,
     pub user_id: String,
     pub group_id: String,
     pub dm_id: String,
-    pub input: String,
-    pub input_pos: usize,
-    pub secret: String,
+    // Message or command being written
+    pub composer: Composer,
+    pub client: GroupMeClient,
+    // Width messages are wrapped to, and the terminal's width it was worked out from
     pub t_width: u16,
+    pub term_width: u16,
+    // Sizes of the panes, from config.toml or as last changed in the TUI
+    pub layout: LayoutConfig,
     pub mode: Modes,
     pub disp: DispMode,
     pub dm: bool,
+    // Set once scrolling back has reached the first message of the conversation
+    pub history_loaded: bool,
+    pub read_state: ReadState,
+    // Id of the message the composed message will reply to
+    pub reply_to: Option<String>,
+    // Local image uploaded and attached to the next message sent
+    pub pending_image: Option<PathBuf>,
+    // Feedback from the last action, shown in the status bar
+    pub status: Option<Status>,
+    // Members of each group, fetched the first time they are needed
+    pub members: HashMap<String, Vec<Member>>,
+    // Nicknames completing the mention being typed
+    pub suggestions: Lists<String>,
+    // Members of the group shown in the members panel
+    pub member_list: Lists<Member>,
+    // Groups, chats and messages from previous sessions
+    pub cache: Cache,
+    // Query being typed in search mode, and the one the results are for
+    pub search_query: String,
+    pub last_search: String,
+    pub search_results: Lists<SearchHit>,
+    // Whether new messages are being pushed to us
+    pub connection: ConnectionState,
+    // Focus and typing for the push listener, None until it's started
+    pub listener: Option<mpsc::Sender<ListenerCommand>>,
+    // Conversation the listener was last told is open
+    pub focused: Option<Conversation>,
+    // People typing in the open conversation by user id, and when they were last seen typing
+    pub typing: Vec<(String, Instant)>,
+    // When the listener was last told the user is typing
+    pub last_typing: Option<Instant>,
 }
 
 #[derive(PartialEq)]
@@ -23,6 +83,14 @@ pub enum Modes {
     DirectNav,
     MessageNav,
     Inputting,
+    MemberNav,
+    Search,
+}
+
+// Shown in the status bar until the next action replaces it
+pub enum Status {
+    Info(String),
+    Error(String),
 }
 
 #[derive(PartialEq)]
@@ -33,96 +101,894 @@ pub enum DispMode {
 }
 
 impl App<'static> {
-    pub fn new(secret: String, t_width: u16) -> App<'static> {
-        let groups = Lists::new(get_groups(secret.to_string()).unwrap(), true);
-        let dms = Lists::new(get_chats(secret.to_string()).unwrap(), true);
-        let group_id = groups.items[groups.state.selected().unwrap()].id.clone();
-        let dm_id = dms.items[dms.state.selected().unwrap()].id.clone();
+    /* Start from whatever is cached so the UI comes up straight away, only waiting on the
+     * network for what has never been cached. sync() brings it up to date afterwards
+     */
+    pub fn new(config: Config, term_width: u16) -> Result<App<'static>> {
+        let client = config.client();
+        let layout = layout_state::load(&config.layout);
+        // Starting in GroupNav, which always shows the sidebar
+        let t_width = layout.message_width(term_width, false);
+        let cache = Cache::open(config.cache_dir())?;
+        let mut from_cache = true;
+        let mut groups = cached_groups(&cache);
+        if groups.is_empty() {
+            groups = get_groups(&client, &cache)?;
+            from_cache = false;
+        }
+        let mut dms = cached_chats(&cache);
+        if dms.is_empty() {
+            dms = get_chats(&client, &cache)?;
+            from_cache = false;
+        }
+        let user_id = match cache.user() {
+            Some(user) => user.id,
+            None => get_userid(&client, &cache)?,
+        };
+        let groups = Lists::new(groups, true);
+        let dms = Lists::new(dms, true);
+        let group_id = groups
+            .items
+            .first()
+            .map(|g| g.id.clone())
+            .unwrap_or_default();
+        let dm_id = dms.items.first().map(|d| d.id.clone()).unwrap_or_default();
         let mut app = App {
             groups,
             dms,
             messages: Lists::new(Vec::new(), false),
-            user_id: get_userid(&secret).unwrap(),
+            config,
+            user_id,
             group_id,
             dm_id,
-            secret,
-            input: "▏".to_string(),
-            input_pos: 0,
+            client,
+            composer: Composer::new(),
             t_width,
+            term_width,
+            layout,
             mode: Modes::GroupNav,
             disp: DispMode::Startup,
             dm: false,
+            history_loaded: false,
+            read_state: ReadState::load(),
+            reply_to: None,
+            pending_image: None,
+            status: None,
+            members: HashMap::new(),
+            suggestions: Lists::new(Vec::new(), false),
+            member_list: Lists::new(Vec::new(), false),
+            cache,
+            search_query: String::new(),
+            last_search: String::new(),
+            search_results: Lists::new(Vec::new(), false),
+            connection: ConnectionState::Connecting,
+            listener: None,
+            focused: None,
+            typing: Vec::new(),
+            last_typing: None,
         };
-        get_messages(&mut app, false).unwrap();
+        app.apply_read_state();
+        if from_cache {
+            get_cached_messages(&mut app, false);
+        } else if !app.group_id.is_empty() {
+            get_messages(&mut app, false)?;
+        }
         app.messages.previous();
-        app
+        Ok(app)
+    }
+
+    /* Fetch whatever changed while the app wasn't running. Failing leaves the cached state up */
+    pub fn sync(&mut self) {
+        if let Err(e) = get_userid(&self.client, &self.cache) {
+            self.show_error(format!("Offline, showing cached messages ({})", e));
+            return;
+        }
+        self.refresh_lists();
+        self.update_msgs();
+    }
+
+    pub fn show_info(&mut self, msg: impl Into<String>) {
+        self.status = Some(Status::Info(msg.into()));
+    }
+
+    pub fn show_error(&mut self, e: impl fmt::Display) {
+        self.status = Some(Status::Error(e.to_string()));
     }
 
     /* Used to update internal message List */
     pub fn update_msgs(&mut self) {
-        self.group_id = self.groups.items[self.groups.state.selected().unwrap()]
-            .id
-            .clone();
-        get_messages(self, false).unwrap();
+        self.group_id = match self.selected_group_id() {
+            Some(id) => id,
+            None => return,
+        };
+        if let Err(e) = get_messages(self, false) {
+            self.show_error(e);
+            return;
+        }
+        self.history_loaded = false;
         self.messages.previous();
+        self.mark_read(&Conversation::Group(self.group_id.clone()));
     }
 
     /* Update internal message list with direct messages */
     pub fn update_dmsgs(&mut self) {
-        self.dm_id = self.dms.items[self.dms.state.selected().unwrap()]
-            .id
-            .clone();
-        get_messages(self, true).unwrap();
+        self.dm_id = match self.selected_dm_id() {
+            Some(id) => id,
+            None => return,
+        };
+        if let Err(e) = get_messages(self, true) {
+            self.show_error(e);
+            return;
+        }
+        self.history_loaded = false;
+        self.messages.previous();
+        self.mark_read(&Conversation::Direct(self.dm_id.clone()));
+    }
+
+    /* Reload the group and direct message lists to pick up new conversations and unread counts,
+     * keeping the current selections
+     */
+    pub fn refresh_lists(&mut self) {
+        let selected_group = self.selected_group_id();
+        let selected_dm = self.selected_dm_id();
+
+        let lists = get_groups(&self.client, &self.cache)
+            .and_then(|groups| Ok((groups, get_chats(&self.client, &self.cache)?)));
+        let (groups, dms) = match lists {
+            Ok(lists) => lists,
+            Err(e) => {
+                self.show_error(e);
+                return;
+            }
+        };
+        self.groups.set_items(groups);
+        self.dms.set_items(dms);
+        let group_pos = self
+            .groups
+            .items
+            .iter()
+            .position(|g| Some(&g.id) == selected_group.as_ref());
+        let dm_pos = self
+            .dms
+            .items
+            .iter()
+            .position(|d| Some(&d.id) == selected_dm.as_ref());
+        self.groups
+            .state
+            .select(group_pos.or_else(|| first(&self.groups)));
+        self.dms.state.select(dm_pos.or_else(|| first(&self.dms)));
+
+        self.apply_read_state();
+    }
+
+    /* Work out unread counts from the stored read markers and order both lists by activity */
+    fn apply_read_state(&mut self) {
+        for group in self.groups.items.iter_mut() {
+            let conv = Conversation::Group(group.id.clone());
+            group.unread = self
+                .read_state
+                .unread(&conv, &group.lmid, group.message_count);
+        }
+        for dm in self.dms.items.iter_mut() {
+            let conv = Conversation::Direct(dm.id.clone());
+            dm.unread = self.read_state.unread(&conv, &dm.lmid, dm.message_count);
+        }
+        self.sort_by_activity();
+        // Not being able to save only means unread counts are off next time
+        let _ = self.read_state.save();
+    }
+
+    fn sort_by_activity(&mut self) {
+        self.groups
+            .sort_by(|a, b| b.last_activity.cmp(&a.last_activity));
+        self.dms
+            .sort_by(|a, b| b.last_activity.cmp(&a.last_activity));
+    }
+
+    /* Clear the unread count of a conversation and remember it as read */
+    fn mark_read(&mut self, conv: &Conversation) {
+        let (lmid, count) = match conv {
+            Conversation::Group(id) => match self.groups.items.iter_mut().find(|g| &g.id == id) {
+                Some(group) => {
+                    group.unread = 0;
+                    (group.lmid.clone(), group.message_count)
+                }
+                None => return,
+            },
+            Conversation::Direct(id) => match self.dms.items.iter_mut().find(|d| &d.id == id) {
+                Some(dm) => {
+                    dm.unread = 0;
+                    (dm.lmid.clone(), dm.message_count)
+                }
+                None => return,
+            },
+        };
+        self.read_state.mark_read(conv, &lmid, count);
+        let _ = self.read_state.save();
+    }
+
+    /* Search cached messages for the typed query, or open the selected hit when the results
+     * are already for that query
+     */
+    pub fn submit_search(&mut self) {
+        if self.search_query != self.last_search || self.search_results.items.is_empty() {
+            self.run_search();
+        } else {
+            self.open_search_hit();
+        }
+    }
+
+    fn run_search(&mut self) {
+        let query = match Query::parse(&self.search_query) {
+            Ok(query) => query,
+            Err(e) => {
+                self.show_error(e);
+                return;
+            }
+        };
+        let mut names = HashMap::new();
+        for group in &self.groups.items {
+            names.insert(Conversation::Group(group.id.clone()), group.name.clone());
+        }
+        for dm in &self.dms.items {
+            names.insert(Conversation::Direct(dm.id.clone()), dm.name.clone());
+        }
+
+        let hits = search(&self.cache, &query, &names);
+        self.show_info(match hits.len() {
+            0 => "No cached messages match".to_string(),
+            1 => "1 result, Enter to open".to_string(),
+            n => format!("{} results, Enter to open", n),
+        });
+        let found = !hits.is_empty();
+        self.search_results.set_items(hits);
+        self.search_results
+            .state
+            .select(if found { Some(0) } else { None });
+        self.last_search = self.search_query.clone();
+    }
+
+    /* Switch to the conversation of the selected hit, showing its messages from the hit on */
+    fn open_search_hit(&mut self) {
+        let hit = match self.search_results.state.selected() {
+            Some(i) => self.search_results.items[i].clone(),
+            None => return,
+        };
+        let opened = match &hit.conversation {
+            Conversation::Group(id) => match self.groups.items.iter().position(|g| &g.id == id) {
+                Some(pos) => {
+                    self.groups.state.select(Some(pos));
+                    self.open_conversation(false);
+                    true
+                }
+                None => false,
+            },
+            Conversation::Direct(id) => match self.dms.items.iter().position(|d| &d.id == id) {
+                Some(pos) => {
+                    self.dms.state.select(Some(pos));
+                    self.open_conversation(true);
+                    true
+                }
+                None => false,
+            },
+        };
+        if !opened {
+            self.show_error(format!(
+                "{} is no longer in your list",
+                hit.conversation_name
+            ));
+            return;
+        }
+
+        let dm = self.dm;
+        if let Some(i) = self
+            .messages
+            .items
+            .iter()
+            .position(|m| m.id == hit.message.id)
+        {
+            self.messages.state.select(Some(i));
+            self.status = None;
+        } else if show_from(self, dm, &hit.message.id) {
+            self.status = None;
+        } else {
+            self.show_error("The message is no longer cached");
+        }
+        self.mode = Modes::MessageNav;
+    }
+
+    /* Show the selected group or direct message, dropping anything composed for another one */
+    pub fn open_conversation(&mut self, dm: bool) {
+        let changed = if dm {
+            self.selected_dm_id().as_ref() != Some(&self.dm_id)
+        } else {
+            self.selected_group_id().as_ref() != Some(&self.group_id)
+        };
+        if changed || dm != self.dm {
+            self.composer.clear();
+            self.reply_to = None;
+            self.pending_image = None;
+        }
+        self.dm = dm;
+        if dm {
+            self.update_dmsgs();
+        } else {
+            self.update_msgs();
+        }
+    }
+
+    /* Select the previous message, loading the page before it when the oldest loaded message is
+     * selected. Wraps around to the newest message once there is no more history
+     */
+    pub fn previous_msg(&mut self) {
+        if self.messages.state.selected() == Some(0) && !self.history_loaded {
+            let dm = self.dm;
+            match get_older_messages(self, dm) {
+                Ok(0) => {
+                    self.history_loaded = true;
+                    return;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.show_error(e);
+                    return;
+                }
+            }
+        }
         self.messages.previous();
     }
 
+    /* Handle the input being submitted, running it as a command when it starts with '/'.
+     * Messages that should start with a '/' can be escaped as '//'
+     */
+    pub fn submit(&mut self) {
+        self.status = None;
+        if self.composer.is_command() {
+            let line = self.composer.text().to_string();
+            match run_command(self, &line) {
+                Ok(()) => self.composer.clear(),
+                Err(e) => self.show_error(e),
+            }
+            return;
+        }
+        if self.composer.text().starts_with("//") {
+            let text = self.composer.text()[1..].to_string();
+            self.composer.set_text(text);
+        }
+        if self.composer.is_empty() && self.pending_image.is_none() {
+            return;
+        }
+        if self.dm {
+            self.send_dmsg();
+        } else {
+            self.send_msg();
+        }
+    }
+
     /* Send message stored in Input to group stored in GroupID, Updates Stored Messages, and Clears
      * Input
      */
     pub fn send_msg(&mut self) {
-        self.group_id = self.groups.items[self.groups.state.selected().unwrap()]
-            .id
-            .clone();
-        send_message(
-            self.secret.to_string(),
-            self.group_id.to_string(),
-            self.input.to_string(),
-            false,
-        )
-        .unwrap();
-        self.update_msgs();
-        self.input.clear();
+        match self.selected_group_id() {
+            Some(id) => self.group_id = id,
+            None => return,
+        }
+        let group_id = self.group_id.clone();
+        self.send(group_id, false);
     }
 
     pub fn send_dmsg(&mut self) {
-        self.dm_id = self.dms.items[self.dms.state.selected().unwrap()]
-            .id
-            .clone();
-        send_message(
-            self.secret.to_string(),
-            self.dm_id.to_string(),
-            self.input.to_string(),
-            true,
-        )
-        .unwrap();
-        self.update_dmsgs();
-        self.input.clear();
+        match self.selected_dm_id() {
+            Some(id) => self.dm_id = id,
+            None => return,
+        }
+        let dm_id = self.dm_id.clone();
+        self.send(dm_id, true);
+    }
+
+    /* Send the input to a conversation. When sending fails the input, image and reply are all
+     * kept, so it can be sent again
+     */
+    fn send(&mut self, id: String, dm: bool) {
+        let pending_image = self.pending_image.clone();
+        let reply_to = self.reply_to.clone();
+        let sent = self.outgoing_attachments().and_then(|attachments| {
+            send_message(
+                &self.client,
+                id,
+                self.composer.text().to_string(),
+                attachments,
+                dm,
+            )
+        });
+        if let Err(e) = sent {
+            self.pending_image = pending_image;
+            self.reply_to = reply_to;
+            self.show_error(format!("Couldn't send: {}", e));
+            return;
+        }
+        if dm {
+            self.update_dmsgs();
+        } else {
+            self.update_msgs();
+        }
+        self.composer.clear();
+    }
+
+    /* Members of the open group, fetched the first time they are needed. Empty if they
+     * couldn't be fetched, which only means mentions won't work until they can be
+     */
+    pub fn group_members(&mut self) -> &[Member] {
+        let group_id = self.group_id.clone();
+        self.members_of(&group_id).unwrap_or_default()
+    }
+
+    /* Members of a group, fetched the first time they are needed. A failed fetch isn't kept, so
+     * the next call tries again
+     */
+    pub fn members_of(&mut self, group_id: &str) -> Result<&[Member]> {
+        if !self.members.contains_key(group_id) {
+            let members = get_members(&self.client, group_id)?;
+            self.members.insert(group_id.to_string(), members);
+        }
+        Ok(&self.members[group_id])
+    }
+
+    /* Ids of the groups and direct messages given by name or id, matching names ignoring case.
+     * Names that match nothing are kept as they are, in case they're ids not listed yet
+     * names: Group and direct message names or ids, e.g. from config.toml
+     */
+    pub fn conversation_ids(&self, names: &[String]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| {
+                let group = self
+                    .groups
+                    .items
+                    .iter()
+                    .find(|g| g.name.eq_ignore_ascii_case(name))
+                    .map(|g| g.id.clone());
+                let dm = self
+                    .dms
+                    .items
+                    .iter()
+                    .find(|d| d.name.eq_ignore_ascii_case(name))
+                    .map(|d| d.id.clone());
+                group.or(dm).unwrap_or_else(|| name.clone())
+            })
+            .collect()
+    }
+
+    /* Id of the group selected in the groups list */
+    pub fn selected_group_id(&self) -> Option<String> {
+        self.groups
+            .state
+            .selected()
+            .and_then(|i| self.groups.items.get(i))
+            .map(|g| g.id.clone())
+    }
+
+    /* Id of the other user in the direct message selected in the list */
+    pub fn selected_dm_id(&self) -> Option<String> {
+        self.dms
+            .state
+            .selected()
+            .and_then(|i| self.dms.items.get(i))
+            .map(|d| d.id.clone())
+    }
+
+    /* Take a group that was left or destroyed out of the groups list, clearing its messages if
+     * it was open
+     */
+    pub fn drop_group(&mut self, group_id: &str) {
+        if let Some(i) = self.groups.items.iter().position(|g| g.id == group_id) {
+            self.groups.remove(i);
+        }
+        self.members.remove(group_id);
+        if !self.dm && self.group_id == group_id {
+            self.messages.set_items(Vec::new());
+            self.group_id = self.selected_group_id().unwrap_or_default();
+        }
+    }
+
+    /* Fill the members panel with the members of the selected group, owner and admins first
+     * refresh: Fetch the members again instead of using the ones already known
+     */
+    pub fn show_members(&mut self, refresh: bool) {
+        let group_id = match self.selected_group_id() {
+            Some(id) => id,
+            None => return,
+        };
+        if refresh || !self.members.contains_key(&group_id) {
+            match get_members(&self.client, &group_id) {
+                Ok(members) => {
+                    self.members.insert(group_id.clone(), members);
+                }
+                // Members fetched before are still worth showing
+                Err(e) if self.members.contains_key(&group_id) => self.show_error(e),
+                Err(e) => {
+                    self.show_error(e);
+                    return;
+                }
+            }
+        }
+
+        let mut members = self.members[&group_id].clone();
+        members.sort_by_key(|m| (role_rank(m), m.nickname.to_lowercase()));
+        let empty = members.is_empty();
+        self.member_list.set_items(members);
+        if !empty {
+            self.member_list.state.select(Some(0));
+        }
+    }
+
+    /* Recompute mention suggestions for the text before the cursor */
+    pub fn update_suggestions(&mut self) {
+        let before = self.composer.before_cursor().to_string();
+        let names = match mention_query(&before) {
+            Some((_, partial)) if !self.dm => {
+                let user_id = self.user_id.clone();
+                suggestions(self.group_members(), partial, &user_id)
+            }
+            _ => Vec::new(),
+        };
+        let empty = names.is_empty();
+        self.suggestions.set_items(names);
+        if !empty {
+            self.suggestions.state.select(Some(0));
+        }
+    }
+
+    /* Replace the mention being typed with the selected suggestion */
+    pub fn accept_suggestion(&mut self) {
+        let nickname = match self.suggestions.state.selected() {
+            Some(i) => self.suggestions.items[i].clone(),
+            None => return,
+        };
+        if let Some((start, _)) = mention_query(self.composer.before_cursor()) {
+            self.composer.complete(start, &format!("@{} ", nickname));
+        }
+        self.suggestions.set_items(Vec::new());
+    }
+
+    /* Open the input with a '/' typed, ready for a command */
+    pub fn start_command(&mut self) {
+        if self.composer.is_empty() {
+            self.composer.insert('/');
+        }
+        self.disp = DispMode::Main;
+        self.mode = Modes::Inputting;
+    }
+
+    /* Start replying to the selected message */
+    pub fn reply(&mut self) {
+        if let Some(i) = self.messages.state.selected() {
+            self.reply_to = Some(self.messages.items[i].id.clone());
+        }
+    }
+
+    /* Attachments for the message being sent, uploading the pending image and consuming the
+     * pending reply. Nothing is consumed if the upload fails, so sending can be retried
+     */
+    fn outgoing_attachments(&mut self) -> Result<Vec<Attachment>> {
+        let mut attachments = Vec::new();
+        if let Some(path) = &self.pending_image {
+            let url = upload_image(&self.client, path)?;
+            attachments.push(Attachment::Image { url });
+            self.pending_image = None;
+        }
+        if !self.dm {
+            let text = self.composer.text().to_string();
+            if let Some(mentions) = build_mentions(&text, self.group_members()) {
+                attachments.push(mentions);
+            }
+        }
+        if let Some(reply_id) = self.reply_to.take() {
+            // Replies to a reply stay attached to the start of the chain
+            let base_reply_id = self
+                .messages
+                .items
+                .iter()
+                .find(|m| m.id == reply_id)
+                .and_then(|m| m.base_reply_id.clone())
+                .unwrap_or_else(|| reply_id.clone());
+            attachments.push(Attachment::Reply {
+                reply_id,
+                base_reply_id,
+            });
+        }
+        Ok(attachments)
     }
 
     /* Like/unlike selected message through API */
     pub fn like(&mut self) {
-        self.group_id = self.groups.items[self.groups.state.selected().unwrap()]
-            .id
-            .clone();
-        like_message(self, false).unwrap();
-        self.update_msgs();
+        if let Err(e) = like_message(self, false) {
+            self.show_error(format!("Couldn't like: {}", e));
+        }
     }
 
     pub fn dlike(&mut self) {
-        self.dm_id = self.dms.items[self.dms.state.selected().unwrap()]
-            .id
-            .clone();
-        like_message(self, true).unwrap();
-        self.update_dmsgs();
+        if let Err(e) = like_message(self, true) {
+            self.show_error(format!("Couldn't like: {}", e));
+        }
+    }
+
+    /* Change the layout and keep it for next time
+     * change: Edit to the layout, given the terminal's width
+     */
+    pub fn change_layout(&mut self, change: impl FnOnce(&mut LayoutConfig, u16)) {
+        change(&mut self.layout, self.term_width);
+        if let Err(e) = layout_state::save(&self.layout) {
+            self.show_error(format!("Couldn't save the layout: {}", e));
+        }
+        self.relayout();
+    }
+
+    // Go back to the layout config.toml sets
+    pub fn reset_layout(&mut self) {
+        self.layout = self.config.layout.clone();
+        if let Err(e) = layout_state::clear() {
+            self.show_error(format!("Couldn't forget the saved layout: {}", e));
+        }
+        self.relayout();
+    }
+
+    /* Wrap messages again after the terminal or the messages pane changed width
+     * term_width: Width of the terminal
+     */
+    pub fn resized(&mut self, term_width: u16) {
+        self.term_width = term_width;
+        self.relayout();
+    }
+
+    // Wrap messages to the messages pane's width again if it changed, e.g. with the mode
+    pub fn relayout(&mut self) {
+        let t_width = self
+            .layout
+            .message_width(self.term_width, self.sidebar_hidden());
+        if t_width == self.t_width {
+            return;
+        }
+        self.t_width = t_width;
+        for item in self.messages.items.iter_mut() {
+            let quote = item.quote.take();
+            *item = to_msginfo(&item.message, quote, &self.user_id, t_width, &self.config);
+        }
+    }
+
+    /* Conversation whose messages are currently loaded in App.messages */
+    pub fn conversation(&self) -> Conversation {
+        if self.dm {
+            Conversation::Direct(self.dm_id.clone())
+        } else {
+            Conversation::Group(self.group_id.clone())
+        }
+    }
+
+    /* Tell the push listener which conversation is open, so its typing events come through.
+     * Nothing is open until the user has opened something, the startup screen only preselects
+     */
+    pub fn focus_listener(&mut self) {
+        let id = if self.dm { &self.dm_id } else { &self.group_id };
+        let conv = if id.is_empty() || self.disp != DispMode::Main {
+            None
+        } else {
+            Some(self.conversation())
+        };
+        if conv == self.focused {
+            return;
+        }
+        self.typing.clear();
+        if let Some(listener) = &self.listener {
+            let _ = listener.send(ListenerCommand::Focus(conv.clone()));
+        }
+        self.focused = conv;
+    }
+
+    /* Let the open conversation know the user is typing, at most once every TYPING_INTERVAL.
+     * Commands aren't messages, so typing them isn't announced
+     */
+    pub fn typed(&mut self) {
+        if self.composer.is_command() {
+            return;
+        }
+        if self
+            .last_typing
+            .is_some_and(|last| last.elapsed() < TYPING_INTERVAL)
+        {
+            return;
+        }
+        if let Some(listener) = &self.listener {
+            let _ = listener.send(ListenerCommand::Typing);
+        }
+        self.last_typing = Some(Instant::now());
+    }
+
+    /* Apply an event from the push listener. New messages in the open conversation are shown
+     * right away, other conversations have their unread counts bumped
+     */
+    pub fn handle_push(&mut self, event: PushEvent) {
+        match event {
+            PushEvent::Connection(state) => {
+                // Anything sent while disconnected was missed, catch up on unread counts
+                let reconnected = matches!(self.connection, ConnectionState::Reconnecting { .. });
+                if reconnected && state == ConnectionState::Connected {
+                    self.refresh_lists();
+                }
+                self.connection = state;
+            }
+            PushEvent::Message(conv, msg) => {
+                self.record_activity(&conv, &msg);
+                // Whoever sent it has stopped typing
+                self.typing.retain(|(user_id, _)| *user_id != msg.sender_id);
+                if conv != self.conversation() || self.messages.items.iter().any(|m| m.id == msg.id)
+                {
+                    return;
+                }
+                // Only the open conversation is known to be cached up to this message, caching
+                // others would leave a gap the next fetch doesn't fill
+                let _ = self.cache.append_messages(&conv, slice::from_ref(&msg));
+                // Keep following the conversation if the newest message was selected
+                let following = match self.messages.state.selected() {
+                    Some(i) => i + 1 == self.messages.items.len(),
+                    None => true,
+                };
+                let quote = self.quote_for(&conv, &msg);
+                self.messages.items.push(to_msginfo(
+                    &msg,
+                    quote,
+                    &self.user_id,
+                    self.t_width,
+                    &self.config,
+                ));
+                if following {
+                    self.messages
+                        .state
+                        .select(Some(self.messages.items.len() - 1));
+                }
+            }
+            PushEvent::Update(conv, msg) => {
+                if conv != self.conversation() {
+                    return;
+                }
+                let _ = self.cache.append_messages(&conv, slice::from_ref(&msg));
+                if let Some(existing) = self.messages.items.iter_mut().find(|m| m.id == msg.id) {
+                    let quote = existing.quote.take();
+                    *existing = to_msginfo(&msg, quote, &self.user_id, self.t_width, &self.config);
+                }
+                // Replies to an edited message quote what it says now
+                for reply in self.messages.items.iter_mut() {
+                    if reply.reply_to.as_deref() == Some(msg.id.as_str()) {
+                        reply.quote = Some(quote_of(&msg));
+                    }
+                }
+            }
+            PushEvent::Typing(conv, user_id) => {
+                if conv != self.conversation() {
+                    return;
+                }
+                self.typing.retain(|(typist, _)| *typist != user_id);
+                self.typing.push((user_id, Instant::now()));
+            }
+        }
+    }
+
+    /* Excerpt of the message a pushed one replies to. It's usually loaded already, the cache is
+     * only read when it isn't
+     * conv: Conversation the pushed message belongs to
+     * msg: Pushed message
+     */
+    fn quote_for(&self, conv: &Conversation, msg: &groupme::Message) -> Option<String> {
+        let reply_id = msg.reply_id()?;
+        if let Some(parent) = self.messages.items.iter().find(|m| m.id == reply_id) {
+            return Some(quote_of(&parent.message));
+        }
+        self.cache
+            .messages(conv)
+            .iter()
+            .find(|m| m.id == reply_id)
+            .map(quote_of)
+    }
+
+    /* Bump the message count and unread count of the conversation a pushed message belongs to.
+     * Messages in the conversation being viewed, or sent by the user, count as read
+     */
+    fn record_activity(&mut self, conv: &Conversation, msg: &groupme::Message) {
+        let read = (self.disp == DispMode::Main && *conv == self.conversation())
+            || msg.sender_id == self.user_id;
+        match conv {
+            Conversation::Group(id) => match self.groups.items.iter_mut().find(|g| &g.id == id) {
+                Some(group) => {
+                    group.lmid = msg.id.clone();
+                    group.message_count += 1;
+                    group.last_activity = msg.created_at;
+                    group.unread += 1;
+                }
+                None => return,
+            },
+            Conversation::Direct(id) => match self.dms.items.iter_mut().find(|d| &d.id == id) {
+                Some(dm) => {
+                    dm.lmid = msg.id.clone();
+                    dm.message_count += 1;
+                    dm.last_activity = msg.created_at;
+                    dm.unread += 1;
+                }
+                None => return,
+            },
+        }
+        if read {
+            self.mark_read(conv);
+        }
+        self.sort_by_activity();
+    }
+}
+
+// Used while drawing, where the messages' lifetime isn't 'static
+impl App<'_> {
+    /* Who is typing in the open conversation, e.g. "Ana is typing…", dropping anyone who has
+     * stopped
+     */
+    pub fn typing_line(&mut self) -> Option<String> {
+        self.typing
+            .retain(|(_, since)| since.elapsed() < TYPING_TIMEOUT);
+        let names: Vec<String> = self
+            .typing
+            .iter()
+            .map(|(user_id, _)| self.name_of(user_id))
+            .collect();
+        match names.as_slice() {
+            [] => None,
+            [name] => Some(format!("{} is typing…", name)),
+            [first, second] => Some(format!("{} and {} are typing…", first, second)),
+            _ => Some("Several people are typing…".to_string()),
+        }
+    }
+
+    // Whether the sidebar is left out, it's always shown while one of its lists is in use
+    pub fn sidebar_hidden(&self) -> bool {
+        self.layout.sidebar_hidden(self.term_width)
+            && !matches!(self.mode, Modes::GroupNav | Modes::DirectNav)
+    }
+
+    // Name of a user in the open conversation, from its members or the messages they've sent
+    fn name_of(&self, user_id: &str) -> String {
+        if self.dm {
+            if let Some(dm) = self.dms.items.iter().find(|d| d.id == user_id) {
+                return dm.name.clone();
+            }
+        } else if let Some(member) = self
+            .members
+            .get(&self.group_id)
+            .and_then(|members| members.iter().find(|m| m.user_id == user_id))
+        {
+            return member.nickname.clone();
+        }
+        self.messages
+            .items
+            .iter()
+            .rev()
+            .find(|m| m.message.sender_id == user_id)
+            .map(|m| m.name.clone())
+            .unwrap_or_else(|| "Someone".to_string())
+    }
+}
+
+// Selection for a list that lost its selected item
+fn first<T>(list: &Lists<T>) -> Option<usize> {
+    if list.items.is_empty() {
+        None
+    } else {
+        Some(0)
+    }
+}
+
+// Owners sort before admins, who sort before everyone else
+fn role_rank(member: &Member) -> u8 {
+    if member.roles.iter().any(|r| r == "owner") {
+        0
+    } else if member.roles.iter().any(|r| r == "admin") {
+        1
+    } else {
+        2
     }
 }
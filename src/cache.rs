@@ -0,0 +1,134 @@
+use crate::groupme::{Chat, Conversation, Group, Message, User};
+use crate::utils::config::config_path;
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/* On disk copy of groups, chats and messages, so the UI can start without waiting on the
+ * network and history can be read offline. Messages are appended to one JSON lines file per
+ * conversation, later lines replacing earlier ones with the same id (e.g. after a like)
+ */
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /* Open the cache stored in the given directory, creating it when necessary */
+    pub fn open(dir: PathBuf) -> io::Result<Cache> {
+        fs::create_dir_all(dir.join("messages"))?;
+        Ok(Cache { dir })
+    }
+
+    // cache/ next to config.toml
+    pub fn default_dir() -> PathBuf {
+        let mut dir = config_path();
+        dir.push("cache");
+        dir
+    }
+
+    pub fn user(&self) -> Option<User> {
+        self.read("me.json")
+    }
+
+    pub fn save_user(&self, user: &User) -> io::Result<()> {
+        self.write("me.json", user)
+    }
+
+    pub fn groups(&self) -> Vec<Group> {
+        self.read("groups.json").unwrap_or_default()
+    }
+
+    pub fn save_groups(&self, groups: &[Group]) -> io::Result<()> {
+        self.write("groups.json", &groups)
+    }
+
+    pub fn chats(&self) -> Vec<Chat> {
+        self.read("chats.json").unwrap_or_default()
+    }
+
+    pub fn save_chats(&self, chats: &[Chat]) -> io::Result<()> {
+        self.write("chats.json", &chats)
+    }
+
+    /* Every cached message of a conversation, oldest first. Lines that can't be parsed (e.g.
+     * from an interrupted write) are skipped
+     */
+    pub fn messages(&self, conv: &Conversation) -> Vec<Message> {
+        let file = match File::open(self.messages_file(conv)) {
+            Ok(file) => file,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut by_id: HashMap<String, Message> = HashMap::new();
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            if let Ok(msg) = serde_json::from_str::<Message>(&line) {
+                by_id.insert(msg.id.clone(), msg);
+            }
+        }
+        let mut msgs: Vec<Message> = by_id.into_values().collect();
+        msgs.sort_by(|a, b| chronological(a).cmp(&chronological(b)));
+        msgs
+    }
+
+    /* Add new or changed messages of a conversation to the cache */
+    pub fn append_messages(&self, conv: &Conversation, msgs: &[Message]) -> io::Result<()> {
+        if msgs.is_empty() {
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.messages_file(conv))?;
+        let mut lines = String::new();
+        for msg in msgs {
+            lines.push_str(&serde_json::to_string(msg)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())
+    }
+
+    /* Conversations that have cached messages */
+    pub fn conversations(&self) -> Vec<Conversation> {
+        let entries = match fs::read_dir(self.dir.join("messages")) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                let key = name.strip_suffix(".jsonl")?;
+                if let Some(id) = key.strip_prefix("group_") {
+                    Some(Conversation::Group(id.to_string()))
+                } else {
+                    key.strip_prefix("dm_")
+                        .map(|id| Conversation::Direct(id.to_string()))
+                }
+            })
+            .collect()
+    }
+
+    fn messages_file(&self, conv: &Conversation) -> PathBuf {
+        self.dir
+            .join("messages")
+            .join(format!("{}.jsonl", conv.key()))
+    }
+
+    fn read<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        let contents = fs::read_to_string(self.dir.join(name)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn write<T: Serialize + ?Sized>(&self, name: &str, value: &T) -> io::Result<()> {
+        fs::write(self.dir.join(name), serde_json::to_string(value)?)
+    }
+}
+
+// Ids are increasing numbers, so they break ties between messages sent in the same second
+fn chronological(msg: &Message) -> (i64, usize, &str) {
+    (msg.created_at, msg.id.len(), &msg.id)
+}
@@ -0,0 +1,416 @@
+use crate::api::*;
+use crate::cache::Cache;
+use crate::export::{default_file_name, export, Format};
+use crate::groupme::{Attachment, Conversation, GroupMeClient, Message};
+use crate::utils::config::Config;
+use crate::utils::read_state::ReadState;
+
+use crate::error::AppError;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde::Serialize;
+
+pub const USAGE: &str = "Usage:
+    gmtui                       Start the TUI
+    gmtui groups [--json]       List groups
+    gmtui dms [--json]          List direct messages
+    gmtui send (--group <name> | --dm <name>) [--image <file>] [--json] [text...]
+                                Send a message, reading the text from stdin when none is given
+    gmtui tail (--group <name> | --dm <name>) [-n <count>] [--follow] [--json]
+                                Print the latest messages, and with --follow keep printing new ones
+    gmtui like (--group <name> | --dm <name>) [--unlike] <message id>
+                                Like or unlike a message
+    gmtui export <conversation> [--format md|json|html] [--output <file>]
+                                Write the whole history of a group or direct message to a file
+    gmtui help                  Show this message
+
+Groups and direct messages are given by name or id. --json prints one JSON object per line.
+The export format defaults to the output file's extension, or Markdown when neither is given.";
+
+// Seconds between checks for new messages with tail --follow
+const FOLLOW_INTERVAL: u64 = 5;
+
+// What the binary was asked to do
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Tui,
+    Help,
+    Groups {
+        json: bool,
+    },
+    Dms {
+        json: bool,
+    },
+    Send {
+        target: Target,
+        // None reads the text from stdin
+        text: Option<String>,
+        image: Option<PathBuf>,
+        json: bool,
+    },
+    Tail {
+        target: Target,
+        count: u32,
+        follow: bool,
+        json: bool,
+    },
+    Like {
+        target: Target,
+        message_id: String,
+        unlike: bool,
+    },
+    Export {
+        conversation: String,
+        format: Format,
+        output: Option<PathBuf>,
+    },
+}
+
+// Group or direct message named on the command line, not yet looked up
+#[derive(Debug, PartialEq)]
+pub enum Target {
+    Group(String),
+    Dm(String),
+}
+
+/* Parse the arguments the binary was started with, not including the program name
+ * args: Command line arguments
+ */
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(Command::Tui),
+    };
+
+    match command.as_ref() {
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        "groups" => {
+            let args = Args::parse(args, &[JSON])?;
+            args.no_positional()?;
+            Ok(Command::Groups {
+                json: args.flag("json"),
+            })
+        }
+        "dms" => {
+            let args = Args::parse(args, &[JSON])?;
+            args.no_positional()?;
+            Ok(Command::Dms {
+                json: args.flag("json"),
+            })
+        }
+        "send" => {
+            let args = Args::parse(args, &[GROUP, DM, JSON, ("image", Some("-i"), true)])?;
+            let text = args.positional.join(" ");
+            Ok(Command::Send {
+                target: args.target()?,
+                text: if text.is_empty() || text == "-" {
+                    None
+                } else {
+                    Some(text)
+                },
+                image: args.value("image").map(PathBuf::from),
+                json: args.flag("json"),
+            })
+        }
+        "tail" => {
+            let args = Args::parse(
+                args,
+                &[
+                    GROUP,
+                    DM,
+                    JSON,
+                    ("follow", Some("-f"), false),
+                    ("count", Some("-n"), true),
+                ],
+            )?;
+            args.no_positional()?;
+            let count = match args.value("count") {
+                Some(count) => match count.parse() {
+                    Ok(count) if (1..=100).contains(&count) => count,
+                    _ => return Err("--count has to be a number from 1 to 100".to_string()),
+                },
+                None => 20,
+            };
+            Ok(Command::Tail {
+                target: args.target()?,
+                count,
+                follow: args.flag("follow"),
+                json: args.flag("json"),
+            })
+        }
+        "like" => {
+            let args = Args::parse(args, &[GROUP, DM, ("unlike", None, false)])?;
+            let message_id = match args.positional.as_slice() {
+                [id] => id.clone(),
+                _ => return Err("like needs exactly one message id".to_string()),
+            };
+            Ok(Command::Like {
+                target: args.target()?,
+                message_id,
+                unlike: args.flag("unlike"),
+            })
+        }
+        "export" => {
+            let args = Args::parse(
+                args,
+                &[("format", Some("-f"), true), ("output", Some("-o"), true)],
+            )?;
+            let conversation = match args.positional.as_slice() {
+                [conversation] => conversation.clone(),
+                [] => return Err("export needs a group or direct message".to_string()),
+                _ => return Err("export takes a single group or direct message".to_string()),
+            };
+            let output = args.value("output").map(PathBuf::from);
+            let format = match args.value("format") {
+                Some(name) => {
+                    Format::from_name(name).ok_or(format!("Unknown export format {}", name))?
+                }
+                None => output
+                    .as_ref()
+                    .and_then(|path| path.extension())
+                    .and_then(|ext| Format::from_name(&ext.to_string_lossy()))
+                    .unwrap_or(Format::Markdown),
+            };
+            Ok(Command::Export {
+                conversation,
+                format,
+                output,
+            })
+        }
+        _ => Err(format!("Unknown command {}", command)),
+    }
+}
+
+// Option a subcommand accepts: long name without the dashes, short form, whether it takes a value
+type OptionSpec = (&'static str, Option<&'static str>, bool);
+
+const GROUP: OptionSpec = ("group", Some("-g"), true);
+const DM: OptionSpec = ("dm", Some("-d"), true);
+const JSON: OptionSpec = ("json", None, false);
+
+struct Args {
+    positional: Vec<String>,
+    // Options that were given, switches map to an empty string
+    options: HashMap<&'static str, String>,
+}
+
+impl Args {
+    fn parse<I: Iterator<Item = String>>(mut args: I, spec: &[OptionSpec]) -> Result<Args, String> {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            options: HashMap::new(),
+        };
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                // Everything after -- is text, even if it starts with a dash
+                parsed.positional.extend(args.by_ref());
+                break;
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                parsed.positional.push(arg);
+                continue;
+            }
+            let (name, _, takes_value) = spec
+                .iter()
+                .find(|(long, short, _)| {
+                    arg.strip_prefix("--") == Some(long) || *short == Some(arg.as_ref())
+                })
+                .ok_or(format!("Unknown option {}", arg))?;
+            let value = if *takes_value {
+                args.next().ok_or(format!("{} needs a value", arg))?
+            } else {
+                String::new()
+            };
+            parsed.options.insert(name, value);
+        }
+        Ok(parsed)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_ref())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    fn target(&self) -> Result<Target, String> {
+        match (self.value("group"), self.value("dm")) {
+            (Some(group), None) => Ok(Target::Group(group.to_string())),
+            (None, Some(dm)) => Ok(Target::Dm(dm.to_string())),
+            (Some(_), Some(_)) => Err("Give either --group or --dm, not both".to_string()),
+            (None, None) => Err("--group or --dm is needed".to_string()),
+        }
+    }
+
+    fn no_positional(&self) -> Result<(), String> {
+        match self.positional.first() {
+            Some(arg) => Err(format!("Unexpected argument {}", arg)),
+            None => Ok(()),
+        }
+    }
+}
+
+/* Run a command that doesn't need the TUI
+ * command: Parsed command, anything but Command::Tui
+ * config: User's configuration
+ */
+pub fn run(command: Command, config: &Config) -> Result<(), AppError> {
+    let client = config.client();
+    let cache = Cache::open(config.cache_dir())?;
+    match command {
+        Command::Tui | Command::Help => println!("{}", USAGE),
+        Command::Groups { json } => {
+            let mut read_state = ReadState::load();
+            for mut group in get_groups(&client, &cache)? {
+                let conv = Conversation::Group(group.id.clone());
+                group.unread = read_state.unread(&conv, &group.lmid, group.message_count);
+                print_item(&group, json, &group.id, &group.name, group.unread)?;
+            }
+        }
+        Command::Dms { json } => {
+            let mut read_state = ReadState::load();
+            for mut dm in get_chats(&client, &cache)? {
+                let conv = Conversation::Direct(dm.id.clone());
+                dm.unread = read_state.unread(&conv, &dm.lmid, dm.message_count);
+                print_item(&dm, json, &dm.id, &dm.name, dm.unread)?;
+            }
+        }
+        Command::Send {
+            target,
+            text,
+            image,
+            json,
+        } => {
+            let (conv, _) = resolve(&client, &cache, &target)?;
+            let text = match text {
+                Some(text) => text,
+                None => {
+                    let mut text = String::new();
+                    io::stdin().read_to_string(&mut text)?;
+                    text.trim_end().to_string()
+                }
+            };
+            let mut attachments = Vec::new();
+            if let Some(path) = image {
+                attachments.push(Attachment::Image {
+                    url: upload_image(&client, &path)?,
+                });
+            }
+            if text.is_empty() && attachments.is_empty() {
+                return Err("Nothing to send".into());
+            }
+            let sent = send_message(
+                &client,
+                conv.id().to_string(),
+                text,
+                attachments,
+                conv.is_direct(),
+            )?;
+            if json {
+                println!("{}", serde_json::to_string(&sent)?);
+            }
+        }
+        Command::Tail {
+            target,
+            count,
+            follow,
+            json,
+        } => {
+            let (conv, _) = resolve(&client, &cache, &target)?;
+            let mut last_id = None;
+            let mut since_id = None;
+            let mut limit = count;
+            loop {
+                for msg in recent_messages(&client, &conv, since_id.take(), limit)? {
+                    print_message(&msg, json)?;
+                    last_id = Some(msg.id);
+                }
+                if !follow {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(FOLLOW_INTERVAL));
+                since_id = last_id.clone();
+                limit = 100;
+            }
+        }
+        Command::Like {
+            target,
+            message_id,
+            unlike,
+        } => {
+            let (conv, _) = resolve(&client, &cache, &target)?;
+            if unlike {
+                client.unlike(conv.id(), &message_id)?;
+            } else {
+                client.like(conv.id(), &message_id)?;
+            }
+        }
+        Command::Export {
+            conversation,
+            format,
+            output,
+        } => {
+            let (conv, name) = find_conversation(&client, &cache, &conversation)?;
+            let path = output.unwrap_or_else(|| default_file_name(&name, format).into());
+            let count = export(&client, &conv, &name, format, &path)?;
+            println!("Exported {} messages to {}", count, path.display());
+        }
+    }
+    Ok(())
+}
+
+fn resolve(
+    client: &GroupMeClient,
+    cache: &Cache,
+    target: &Target,
+) -> Result<(Conversation, String), AppError> {
+    match target {
+        Target::Group(name) => find_group(client, cache, name),
+        Target::Dm(name) => find_dm(client, cache, name),
+    }
+}
+
+// Group or direct message as "id<TAB>name", with the unread count when there is one
+fn print_item<T: Serialize>(
+    item: &T,
+    json: bool,
+    id: &str,
+    name: &str,
+    unread: u64,
+) -> Result<(), AppError> {
+    if json {
+        println!("{}", serde_json::to_string(item)?);
+    } else if unread > 0 {
+        println!("{}\t{} ({} unread)", id, name, unread);
+    } else {
+        println!("{}\t{}", id, name);
+    }
+    Ok(())
+}
+
+// Message as "[time] name: text", attachments and further lines of text indented below
+fn print_message(msg: &Message, json: bool) -> Result<(), AppError> {
+    if json {
+        println!("{}", serde_json::to_string(msg)?);
+        return Ok(());
+    }
+    let sent = Local.timestamp(msg.created_at, 0).format("%Y-%m-%d %H:%M");
+    let mut lines = msg.text.as_deref().unwrap_or("").lines();
+    println!("[{}] {}: {}", sent, msg.name, lines.next().unwrap_or(""));
+    for line in lines {
+        println!("    {}", line);
+    }
+    for attachment in &msg.attachments {
+        if let Some(line) = attachment_line(attachment, msg.event.as_ref()) {
+            println!("    {}", line);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,249 @@
+use crate::api::*;
+use crate::app::App;
+use crate::error::Result;
+use crate::export::{default_file_name, export, Format};
+use crate::groupme::{Conversation, GroupUpdate, NewMember};
+use crate::lists::GroupInfo;
+
+use std::path::PathBuf;
+
+/* Run a command typed into the input box, e.g. "/attach ~/cat.png". Returns an error to show
+ * the user when the command can't be run
+ * app: Main application object
+ * line: Input, including the leading '/'
+ */
+pub fn run_command(app: &mut App<'static>, line: &str) -> Result<()> {
+    let line = line.trim_start_matches('/');
+    let (command, args) = match line.find(' ') {
+        Some(i) => (&line[..i], line[i + 1..].trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        "attach" => attach(app, args),
+        "detach" => {
+            app.pending_image = None;
+            Ok(())
+        }
+        "group" => group(app, args),
+        "export" => export_conversation(app, args),
+        _ => Err(format!("Unknown command /{}", command).into()),
+    }
+}
+
+/* Attach an image to the next message sent
+ * args: Path to the image, ~ is expanded to the home directory
+ */
+fn attach(app: &mut App<'static>, args: &str) -> Result<()> {
+    if args.is_empty() {
+        return Err("Usage: /attach <path to image>".into());
+    }
+    let path = expand_home(args);
+    if !path.is_file() {
+        return Err(format!("{} is not a file", path.display()).into());
+    }
+    if image_type(&path).is_none() {
+        return Err(format!("{} is not a supported image", path.display()).into());
+    }
+    app.pending_image = Some(path);
+    Ok(())
+}
+
+/* Write the open conversation's whole history to a file
+ * args: Format (md, json or html) optionally followed by the file to write, which defaults to
+ * the conversation's name in the current directory
+ */
+fn export_conversation(app: &mut App<'static>, args: &str) -> Result<()> {
+    let (format, path) = match args.find(' ') {
+        Some(i) => (&args[..i], args[i + 1..].trim()),
+        None => (args, ""),
+    };
+    let format = Format::from_name(format).ok_or("Usage: /export <md|json|html> [path]")?;
+
+    let conv = app.conversation();
+    let name = match &conv {
+        Conversation::Group(id) => app
+            .groups
+            .items
+            .iter()
+            .find(|g| &g.id == id)
+            .map(|g| &g.name),
+        Conversation::Direct(id) => app.dms.items.iter().find(|d| &d.id == id).map(|d| &d.name),
+    }
+    .cloned()
+    .unwrap_or_else(|| conv.id().to_string());
+    let path = if path.is_empty() {
+        PathBuf::from(default_file_name(&name, format))
+    } else {
+        expand_home(path)
+    };
+
+    let count = export(&app.client, &conv, &name, format, &path)?;
+    app.show_info(format!("Exported {} messages to {}", count, path.display()));
+    Ok(())
+}
+
+/* Manage the group selected in the groups list, or create a new one
+ * args: Subcommand followed by its arguments, e.g. "rename Book Club"
+ */
+fn group(app: &mut App<'static>, args: &str) -> Result<()> {
+    let (sub, rest) = match args.find(' ') {
+        Some(i) => (&args[..i], args[i + 1..].trim()),
+        None => (args, ""),
+    };
+
+    if sub == "create" {
+        if rest.is_empty() {
+            return Err("Usage: /group create <name>".into());
+        }
+        let group = create_group(&app.client, rest)?;
+        app.groups.prepend_items(vec![group]);
+        return Ok(());
+    }
+
+    // The groups list keeps its selection while a direct message is open, acting on it then
+    // would change a group the user can't see
+    if app.dm {
+        return Err(format!("Open a group to use /group {}", sub).into());
+    }
+    let group_id = app.selected_group_id().ok_or("No group selected")?;
+    match sub {
+        "rename" | "describe" | "image" if rest.is_empty() => {
+            Err(format!("Usage: /group {} <value>", sub).into())
+        }
+        "rename" => update(
+            app,
+            &group_id,
+            GroupUpdate {
+                name: Some(rest.to_string()),
+                ..Default::default()
+            },
+        ),
+        "describe" => update(
+            app,
+            &group_id,
+            GroupUpdate {
+                description: Some(rest.to_string()),
+                ..Default::default()
+            },
+        ),
+        "image" => {
+            // Local files are uploaded first, anything else is taken to be a url
+            let path = expand_home(rest);
+            let image_url = if path.is_file() {
+                upload_image(&app.client, &path)?
+            } else {
+                rest.to_string()
+            };
+            update(
+                app,
+                &group_id,
+                GroupUpdate {
+                    image_url: Some(image_url),
+                    ..Default::default()
+                },
+            )
+        }
+        "add" => {
+            if rest.is_empty() {
+                return Err("Usage: /group add <contact name or user id>".into());
+            }
+            // Contacts are the people the user has direct messages with
+            let member = match app
+                .dms
+                .items
+                .iter()
+                .find(|d| d.name.eq_ignore_ascii_case(rest))
+            {
+                Some(contact) => NewMember {
+                    nickname: contact.name.clone(),
+                    user_id: contact.id.clone(),
+                },
+                None if rest.chars().all(|c| c.is_ascii_digit()) => NewMember {
+                    nickname: rest.to_string(),
+                    user_id: rest.to_string(),
+                },
+                None => return Err(format!("No contact named {}", rest).into()),
+            };
+            add_members(&app.client, &group_id, vec![member])?;
+            app.members.remove(&group_id);
+            Ok(())
+        }
+        "remove" => {
+            if rest.is_empty() {
+                return Err("Usage: /group remove <nickname>".into());
+            }
+            let membership_id = app
+                .members_of(&group_id)?
+                .iter()
+                .find(|m| m.nickname.eq_ignore_ascii_case(rest))
+                .map(|m| m.id.clone())
+                .ok_or(format!("No member named {}", rest))?;
+            remove_member(&app.client, &group_id, &membership_id)?;
+            app.members.remove(&group_id);
+            Ok(())
+        }
+        "leave" => {
+            confirm(app, &group_id, sub, rest)?;
+            let user_id = app.user_id.clone();
+            let membership_id = app
+                .members_of(&group_id)?
+                .iter()
+                .find(|m| m.user_id == user_id)
+                .map(|m| m.id.clone())
+                .ok_or("Couldn't find your membership")?;
+            remove_member(&app.client, &group_id, &membership_id)?;
+            app.drop_group(&group_id);
+            Ok(())
+        }
+        "destroy" => {
+            confirm(app, &group_id, sub, rest)?;
+            destroy_group(&app.client, &group_id)?;
+            app.drop_group(&group_id);
+            Ok(())
+        }
+        _ => Err("Usage: /group create|rename|describe|image|add|remove|leave|destroy ...".into()),
+    }
+}
+
+/* Leaving and destroying can't be undone, so the group's name has to be typed out after them
+ * group_id: Group the subcommand acts on
+ * sub: Subcommand being confirmed
+ * typed: What was typed after it
+ */
+fn confirm(app: &App<'static>, group_id: &str, sub: &str, typed: &str) -> Result<()> {
+    let name = app
+        .groups
+        .items
+        .iter()
+        .find(|g| g.id == group_id)
+        .map(|g| g.name.as_str())
+        .unwrap_or_default();
+    if typed != name {
+        return Err(format!("Type /group {} {} to confirm", sub, name).into());
+    }
+    Ok(())
+}
+
+/* Apply an update to a group and show the result in the groups list */
+fn update(app: &mut App<'static>, group_id: &str, update: GroupUpdate) -> Result<()> {
+    let updated = update_group(&app.client, &app.cache, group_id, &update)?;
+    if let Some(group) = app.groups.items.iter_mut().find(|g| g.id == group_id) {
+        // Unread messages are counted here, the server doesn't know about them
+        *group = GroupInfo {
+            unread: group.unread,
+            ..updated
+        };
+    }
+    Ok(())
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(mut home)) => {
+            home.push(rest);
+            home
+        }
+        _ => PathBuf::from(path),
+    }
+}
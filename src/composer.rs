@@ -0,0 +1,469 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Most edits that can be undone
+const UNDO_LIMIT: usize = 100;
+
+// Message being written, with a cursor that moves a grapheme at a time and an undo history
+#[derive(Default)]
+pub struct Composer {
+    text: String,
+    // Byte offset into text, always on a grapheme boundary
+    cursor: usize,
+    // Text and cursor before each undoable edit, most recent last
+    history: Vec<(String, usize)>,
+    // Kind of the last edit, so a run of typing is undone in one go
+    last_edit: Option<Edit>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Edit {
+    Insert,
+    Delete,
+    // Kills, completions and anything else that's always undone on its own
+    Replace,
+}
+
+// Text wrapped to the width of the input box, and where the cursor is in it
+pub struct Wrapped {
+    pub rows: Vec<String>,
+    // Column and row of the cursor
+    pub cursor: (u16, u16),
+}
+
+impl Composer {
+    pub fn new() -> Composer {
+        Composer::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    // Commands start with a '/', messages that should start with one are escaped as "//"
+    pub fn is_command(&self) -> bool {
+        self.text.starts_with('/') && !self.text.starts_with("//")
+    }
+
+    // Text up to the cursor, e.g. to find the mention being typed
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    // Replace everything with new text, cursor at the end. Undo brings back what was there
+    pub fn set_text(&mut self, text: String) {
+        self.checkpoint(Edit::Replace);
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    // Start over once a message is sent, forgetting its history
+    pub fn clear(&mut self) {
+        *self = Composer::default();
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.checkpoint(Edit::Insert);
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn newline(&mut self) {
+        self.insert('\n');
+    }
+
+    pub fn backspace(&mut self) {
+        let start = self.previous_boundary(self.cursor);
+        if start < self.cursor {
+            self.checkpoint(Edit::Delete);
+            self.text.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+    }
+
+    pub fn delete(&mut self) {
+        let end = self.next_boundary(self.cursor);
+        if end > self.cursor {
+            self.checkpoint(Edit::Delete);
+            self.text.replace_range(self.cursor..end, "");
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.move_to(self.previous_boundary(self.cursor));
+    }
+
+    pub fn right(&mut self) {
+        self.move_to(self.next_boundary(self.cursor));
+    }
+
+    pub fn word_left(&mut self) {
+        self.move_to(self.word_start());
+    }
+
+    // Past the end of the next word, as readline does
+    pub fn word_right(&mut self) {
+        let mut pos = self.cursor;
+        let mut in_word = false;
+        for grapheme in self.text[self.cursor..].graphemes(true) {
+            let space = is_space(grapheme);
+            if in_word && space {
+                break;
+            }
+            in_word |= !space;
+            pos += grapheme.len();
+        }
+        self.move_to(pos);
+    }
+
+    // Start of the line the cursor is on
+    pub fn home(&mut self) {
+        self.move_to(self.line_start(self.cursor));
+    }
+
+    pub fn end(&mut self) {
+        self.move_to(self.line_end(self.cursor));
+    }
+
+    // Up a line, keeping to the same column where the line is long enough
+    pub fn up(&mut self) {
+        let start = self.line_start(self.cursor);
+        if start > 0 {
+            let column = self.text[start..self.cursor].width();
+            let above = self.line_start(start - 1);
+            self.move_to(self.at_column(above, column));
+        }
+    }
+
+    pub fn down(&mut self) {
+        let end = self.line_end(self.cursor);
+        if end < self.text.len() {
+            let column = self.text[self.line_start(self.cursor)..self.cursor].width();
+            self.move_to(self.at_column(end + 1, column));
+        }
+    }
+
+    // Delete the word before the cursor, Ctrl+W in a shell
+    pub fn kill_word(&mut self) {
+        let start = self.word_start();
+        if start < self.cursor {
+            self.checkpoint(Edit::Replace);
+            self.text.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+    }
+
+    // Delete from the start of the line to the cursor, Ctrl+U in a shell
+    pub fn kill_line(&mut self) {
+        let start = self.line_start(self.cursor);
+        if start < self.cursor {
+            self.checkpoint(Edit::Replace);
+            self.text.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+    }
+
+    /* Replace the text from start to the cursor, leaving the cursor after the replacement
+     * start: Byte offset the replaced text starts at, e.g. the @ of a mention
+     * replacement: Text to put in its place
+     */
+    pub fn complete(&mut self, start: usize, replacement: &str) {
+        if start <= self.cursor && self.text.is_char_boundary(start) {
+            self.checkpoint(Edit::Replace);
+            self.text.replace_range(start..self.cursor, replacement);
+            self.cursor = start + replacement.len();
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((text, cursor)) = self.history.pop() {
+            self.text = text;
+            self.cursor = cursor;
+        }
+        self.last_edit = None;
+    }
+
+    /* Break the text into rows that fit the input box, a grapheme at a time
+     * width: Columns available inside the input box's borders
+     */
+    pub fn wrap(&self, width: u16) -> Wrapped {
+        let width = width.max(1) as usize;
+        let mut rows = vec![String::new()];
+        let mut column = 0;
+        let mut cursor = None;
+        for (i, grapheme) in self.text.grapheme_indices(true) {
+            if i == self.cursor {
+                cursor = Some((column, rows.len() - 1));
+            }
+            if grapheme == "\n" || grapheme == "\r\n" {
+                rows.push(String::new());
+                column = 0;
+                continue;
+            }
+            let grapheme_width = grapheme.width();
+            if column + grapheme_width > width && column > 0 {
+                rows.push(String::new());
+                column = 0;
+                if i == self.cursor {
+                    cursor = Some((0, rows.len() - 1));
+                }
+            }
+            if let Some(row) = rows.last_mut() {
+                row.push_str(grapheme);
+            }
+            column += grapheme_width;
+        }
+        let (mut column, mut row) = cursor.unwrap_or((column, rows.len() - 1));
+        // A cursor after a full row would sit on the border, so it starts the next one
+        if column >= width {
+            column = 0;
+            row += 1;
+            if row == rows.len() {
+                rows.push(String::new());
+            }
+        }
+        Wrapped {
+            rows,
+            cursor: (column as u16, row as u16),
+        }
+    }
+
+    // Remember the text before an edit, unless it carries on a run of the same kind of edit
+    fn checkpoint(&mut self, edit: Edit) {
+        if edit == Edit::Replace || self.last_edit != Some(edit) {
+            self.history.push((self.text.clone(), self.cursor));
+            if self.history.len() > UNDO_LIMIT {
+                self.history.remove(0);
+            }
+        }
+        self.last_edit = Some(edit);
+    }
+
+    // Moving ends a run of typing, so the next one is undone separately
+    fn move_to(&mut self, pos: usize) {
+        self.cursor = pos;
+        self.last_edit = None;
+    }
+
+    fn previous_boundary(&self, pos: usize) -> usize {
+        self.text[..pos]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .graphemes(true)
+            .next()
+            .map_or(pos, |grapheme| pos + grapheme.len())
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .find('\n')
+            .map_or(self.text.len(), |i| pos + i)
+    }
+
+    // Start of the word before the cursor, skipping any spaces between them
+    fn word_start(&self) -> usize {
+        let mut start = self.cursor;
+        let mut in_word = false;
+        for (i, grapheme) in self.text[..self.cursor].grapheme_indices(true).rev() {
+            let space = is_space(grapheme);
+            if in_word && space {
+                break;
+            }
+            in_word |= !space;
+            start = i;
+        }
+        start
+    }
+
+    /* Position on the line starting at start that's closest to a column without passing it
+     * start: Byte offset of the start of the line
+     * column: Display column to aim for
+     */
+    fn at_column(&self, start: usize, column: usize) -> usize {
+        let end = self.line_end(start);
+        let mut pos = start;
+        let mut width = 0;
+        for grapheme in self.text[start..end].graphemes(true) {
+            width += grapheme.width();
+            if width > column {
+                break;
+            }
+            pos += grapheme.len();
+        }
+        pos
+    }
+}
+
+fn is_space(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn composer(text: &str) -> Composer {
+        let mut composer = Composer::new();
+        for c in text.chars() {
+            composer.insert(c);
+        }
+        composer
+    }
+
+    #[test]
+    fn cursor_moves_over_combining_marks() {
+        // "é" written as e followed by a combining acute accent
+        let mut composer = composer("ae\u{301}b");
+        composer.home();
+        composer.right();
+        composer.right();
+        assert_eq!(composer.before_cursor(), "ae\u{301}");
+        composer.left();
+        assert_eq!(composer.before_cursor(), "a");
+        composer.delete();
+        assert_eq!(composer.text(), "ab");
+    }
+
+    #[test]
+    fn emoji_sequence_is_one_grapheme() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let mut composer = composer(&format!("hi {}", family));
+        composer.left();
+        assert_eq!(composer.before_cursor(), "hi ");
+        composer.right();
+        composer.backspace();
+        assert_eq!(composer.text(), "hi ");
+    }
+
+    #[test]
+    fn word_movement_skips_spaces() {
+        let mut composer = composer("one  two three");
+        composer.word_left();
+        assert_eq!(composer.before_cursor(), "one  two ");
+        composer.word_left();
+        composer.word_left();
+        assert_eq!(composer.before_cursor(), "");
+        composer.word_right();
+        assert_eq!(composer.before_cursor(), "one");
+        composer.word_right();
+        assert_eq!(composer.before_cursor(), "one  two");
+    }
+
+    #[test]
+    fn kill_word_at_line_start_takes_the_word_before_the_newline() {
+        let mut composer = composer("one two\nthree");
+        composer.home();
+        composer.kill_word();
+        assert_eq!(composer.text(), "one three");
+        assert_eq!(composer.before_cursor(), "one ");
+    }
+
+    #[test]
+    fn kill_word_at_the_start_does_nothing() {
+        let mut composer = composer("one");
+        composer.home();
+        composer.kill_word();
+        assert_eq!(composer.text(), "one");
+    }
+
+    #[test]
+    fn kill_line_stops_at_the_newline() {
+        let mut composer = composer("one\ntwo three");
+        composer.kill_line();
+        assert_eq!(composer.text(), "one\n");
+        // Nothing before the cursor on this line, so the newline stays
+        composer.kill_line();
+        assert_eq!(composer.text(), "one\n");
+    }
+
+    #[test]
+    fn undo_brings_back_what_was_killed() {
+        let mut composer = composer("one two");
+        composer.kill_word();
+        assert_eq!(composer.text(), "one ");
+        composer.undo();
+        assert_eq!(composer.text(), "one two");
+        assert_eq!(composer.before_cursor(), "one two");
+    }
+
+    #[test]
+    fn run_of_typing_is_undone_at_once() {
+        let mut composer = composer("one");
+        composer.left();
+        composer.insert('x');
+        composer.insert('y');
+        composer.undo();
+        assert_eq!(composer.text(), "one");
+        composer.undo();
+        assert_eq!(composer.text(), "");
+    }
+
+    #[test]
+    fn undo_keeps_only_the_latest_edits() {
+        let mut composer = Composer::new();
+        for i in 0..UNDO_LIMIT + 10 {
+            composer.set_text(i.to_string());
+        }
+        for _ in 0..UNDO_LIMIT + 10 {
+            composer.undo();
+        }
+        // The 10 oldest edits were forgotten, so undoing stops at what the 10th one replaced
+        assert_eq!(composer.text(), "9");
+    }
+
+    #[test]
+    fn wrap_uses_display_width() {
+        // Three characters, but six columns
+        let mut composer = composer("日本語");
+        let wrapped = composer.wrap(4);
+        assert_eq!(wrapped.rows, ["日本", "語"]);
+        assert_eq!(wrapped.cursor, (2, 1));
+        let wrapped = composer.wrap(5);
+        assert_eq!(wrapped.rows, ["日本", "語"]);
+        composer.home();
+        let wrapped = composer.wrap(6);
+        assert_eq!(wrapped.rows, ["日本語"]);
+        assert_eq!(wrapped.cursor, (0, 0));
+    }
+
+    #[test]
+    fn cursor_after_a_full_row_starts_the_next() {
+        let composer = composer("abcd");
+        let wrapped = composer.wrap(4);
+        assert_eq!(wrapped.rows, ["abcd", ""]);
+        assert_eq!(wrapped.cursor, (0, 1));
+    }
+
+    #[test]
+    fn wrap_breaks_at_newlines() {
+        let mut composer = composer("ab\ncd");
+        composer.up();
+        let wrapped = composer.wrap(10);
+        assert_eq!(wrapped.rows, ["ab", "cd"]);
+        assert_eq!(wrapped.cursor, (2, 0));
+    }
+
+    #[test]
+    fn up_and_down_keep_the_display_column() {
+        let mut composer = composer("日本\nabcd");
+        composer.up();
+        assert_eq!(composer.before_cursor(), "日本");
+        composer.left();
+        composer.down();
+        // 日 is two columns wide, so the cursor lands after ab
+        assert_eq!(composer.before_cursor(), "日本\nab");
+    }
+}
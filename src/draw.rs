@@ -1,43 +1,51 @@
+use crate::api::quote_of;
 use crate::app::*;
+use crate::composer::Wrapped;
+use crate::keys::{mode_name, ACTIONS, NAV_MODES};
+use crate::listener::ConnectionState;
+use crate::search::{highlights, Query, SearchHit};
+use crate::theme::Theme;
+use crate::utils::config::LayoutConfig;
 use crate::utils::*;
 
 use std::io;
 
+use chrono::prelude::*;
+use textwrap::fill;
+
 use tui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::Modifier,
     terminal::Frame,
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
     Terminal,
 };
 
-fn draw_startup(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut App) {
+fn draw_startup(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+) -> io::Result<()> {
     terminal
         .draw(|f| {
             let main_chunks = Layout::default()
                 .direction(Direction::Horizontal)
                 .margin(1)
-                .constraints(
-                    [
-                        Constraint::Percentage(5),
-                        Constraint::Percentage(15),
-                        Constraint::Percentage(75),
-                        Constraint::Percentage(5),
-                    ]
-                    .as_ref(),
-                )
+                .constraints(columns(&app.layout, app.sidebar_hidden()))
                 .split(f.size());
             let left_chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+                .constraints(rows(app.layout.groups))
                 .split(main_chunks[1]);
             let right_chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([Constraint::Percentage(15), Constraint::Percentage(85)].as_ref())
                 .split(main_chunks[2]);
-            render_groups(f, app, left_chunks[0]);
-            render_direct(f, app, left_chunks[1]);
+            if !app.sidebar_hidden() {
+                render_groups(f, app, left_chunks[0]);
+                render_direct(f, app, left_chunks[1]);
+            }
             let logo = Paragraph::new(logo::LOGO)
                 .block(Block::default().borders(Borders::LEFT | Borders::RIGHT | Borders::TOP));
             let changelog = Paragraph::new(include_str!("../CHANGELOG.md").to_string())
@@ -45,10 +53,114 @@ fn draw_startup(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut
             f.render_widget(logo, right_chunks[0]);
             f.render_widget(changelog, right_chunks[1]);
         })
-        .expect("Bad Term")
+        .map(|_| ())
 }
 
-fn draw_help(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, _app: &mut App) {
+// Margin, sidebar, messages and margin, sized as the layout says
+fn columns(layout: &LayoutConfig, hidden: bool) -> Vec<Constraint> {
+    layout
+        .columns(hidden)
+        .iter()
+        .map(|&percent| Constraint::Percentage(percent))
+        .collect()
+}
+
+// Two rows, the first taking the given percentage of the height
+fn rows(first: u16) -> Vec<Constraint> {
+    vec![
+        Constraint::Percentage(first),
+        Constraint::Percentage(100 - first),
+    ]
+}
+
+fn draw_help(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+) -> io::Result<()> {
+    let theme = &app.config.theme;
+    let bound = |mode: &Modes, layout: bool| -> Vec<(String, &str)> {
+        ACTIONS
+            .iter()
+            .filter(|action| action.is_layout() == layout)
+            .filter_map(|action| {
+                let keys = app.config.keybindings.keys(*action);
+                match action.describe(mode) {
+                    Some(description) if !keys.is_empty() => {
+                        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+                        Some((keys.join(", "), description))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    };
+    let mut sections: Vec<(&str, Vec<(String, &str)>)> = NAV_MODES
+        .iter()
+        .map(|mode| (mode_name(mode), bound(mode, false)))
+        .collect();
+    // Layout keys do the same in every list, so they're only listed once
+    sections.push(("Layout", bound(&Modes::MessageNav, true)));
+    // Typing keys can't be rebound
+    sections.push((
+        mode_name(&Modes::Inputting),
+        vec![
+            ("Enter".to_string(), "Send, or run a /command"),
+            ("Shift+Enter, Alt+Enter".to_string(), "New line"),
+            ("Arrows".to_string(), "Move the cursor"),
+            ("Ctrl+Left, Ctrl+Right".to_string(), "Move a word at a time"),
+            ("Home, End".to_string(), "Start or end of the line"),
+            ("Ctrl+W".to_string(), "Delete the word before the cursor"),
+            ("Ctrl+U".to_string(), "Delete to the start of the line"),
+            ("Ctrl+Z".to_string(), "Undo"),
+            ("Ctrl+O".to_string(), "Continue in $VISUAL or $EDITOR"),
+            ("Tab".to_string(), "Accept the suggestion"),
+            (
+                "Up, Down".to_string(),
+                "Choose a suggestion, when there are any",
+            ),
+            ("Esc".to_string(), "Stop writing, cancelling a reply"),
+        ],
+    ));
+    sections.push((
+        mode_name(&Modes::Search),
+        vec![
+            ("Enter".to_string(), "Search"),
+            ("Up, Down".to_string(), "Choose a result"),
+            ("Esc".to_string(), "Back to the messages"),
+        ],
+    ));
+
+    let key_width = sections
+        .iter()
+        .flat_map(|(_, rows)| rows.iter().map(|(keys, _)| keys.chars().count()))
+        .max()
+        .unwrap_or(0);
+    let mut columns = [Vec::new(), Vec::new()];
+    // Sections fill the left column until it has about half the lines, a title and a blank line
+    // going with each
+    let half: usize = sections
+        .iter()
+        .map(|(_, rows)| rows.len() + 2)
+        .sum::<usize>()
+        / 2;
+    for (title, rows) in sections {
+        let column = &mut columns[if columns[0].len() < half { 0 } else { 1 }];
+        column.push(Spans::from(Span::styled(
+            title,
+            theme.accent.add_modifier(Modifier::BOLD),
+        )));
+        for (keys, description) in rows {
+            column.push(Spans::from(vec![
+                Span::styled(
+                    format!("  {:width$}  ", keys, width = key_width),
+                    theme.name,
+                ),
+                Span::styled(description, theme.text),
+            ]));
+        }
+        column.push(Spans::from(""));
+    }
+
     terminal
         .draw(|f| {
             let main_chunks = Layout::default()
@@ -63,58 +175,193 @@ fn draw_help(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, _app: &mut A
                     .as_ref(),
                 )
                 .split(f.size());
-            let help_menu = Paragraph::new("Help To Be Implemented")
-                .block(Block::default().borders(Borders::ALL).title("Help"));
-            f.render_widget(help_menu, main_chunks[1]);
+            let help_block = Block::default()
+                .borders(Borders::ALL)
+                .title("Help")
+                .border_style(theme.accent);
+            let inner = help_block.inner(main_chunks[1]);
+            f.render_widget(help_block, main_chunks[1]);
+
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(1)
+                .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+                .split(inner);
+            let halves = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(rows[0]);
+            for (column, area) in columns.iter().zip(halves.iter()) {
+                f.render_widget(Paragraph::new(column.clone()), *area);
+            }
+            let footer = Paragraph::new(Span::styled(
+                "Keys are set in [keybindings] of config.toml, Esc to close",
+                theme.dim,
+            ));
+            f.render_widget(footer, rows[1]);
         })
-        .expect("Bad Term");
+        .map(|_| ())
 }
 
-fn draw_main(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut App) {
+fn draw_main(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+) -> io::Result<()> {
     terminal
         .draw(|f| {
             let main_chunks = Layout::default()
                 .direction(Direction::Horizontal)
                 .margin(1)
-                .constraints(
-                    [
-                        Constraint::Percentage(5),
-                        Constraint::Percentage(15),
-                        Constraint::Percentage(75),
-                        Constraint::Percentage(5),
-                    ]
-                    .as_ref(),
-                )
+                .constraints(columns(&app.layout, app.sidebar_hidden()))
                 .split(f.size());
 
             let left_chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+                .constraints(rows(app.layout.groups))
                 .split(main_chunks[1]);
 
+            // The message box grows with what's being written
+            let wrapped = app.composer.wrap(main_chunks[2].width.saturating_sub(2));
+            let lines = if app.mode == Modes::Search {
+                1
+            } else {
+                wrapped.rows.len() as u16
+            };
             let right_chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(94), Constraint::Percentage(6)].as_ref())
+                .constraints(
+                    [
+                        Constraint::Min(0),
+                        Constraint::Length(input_height(&app.layout, main_chunks[2].height, lines)),
+                    ]
+                    .as_ref(),
+                )
                 .split(main_chunks[2]);
-            render_groups(f, app, left_chunks[0]);
-            render_direct(f, app, left_chunks[1]);
-            render_messages(f, app, right_chunks[0]);
-
-            let input_block = Paragraph::new(app.input.as_ref())
-                .style(Style::default().fg(Color::White))
-                .block(
-                    Block::default()
-                        .title("New Message")
-                        .borders(Borders::ALL)
-                        .border_style(if app.mode == Modes::Inputting {
-                            Style::default().fg(Color::Magenta)
-                        } else {
-                            Style::default()
-                        }),
+            if !app.sidebar_hidden() {
+                render_groups(f, app, left_chunks[0]);
+                render_direct(f, app, left_chunks[1]);
+            }
+            match app.mode {
+                Modes::MemberNav => render_members(f, app, right_chunks[0]),
+                Modes::Search => render_search(f, app, right_chunks[0]),
+                _ => match app.typing_line() {
+                    // Who's typing goes on a line of its own under the messages
+                    Some(typing) => {
+                        let message_chunks = Layout::default()
+                            .direction(Direction::Vertical)
+                            .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+                            .split(right_chunks[0]);
+                        render_messages(f, app, message_chunks[0]);
+                        let typing = Paragraph::new(typing).style(app.config.theme.dim);
+                        f.render_widget(typing, message_chunks[1]);
+                    }
+                    None => render_messages(f, app, right_chunks[0]),
+                },
+            }
+
+            let input_block = Block::default().borders(Borders::ALL).border_style(
+                if app.mode == Modes::Inputting || app.mode == Modes::Search {
+                    app.config.theme.accent
+                } else {
+                    app.config.theme.border
+                },
+            );
+            // The input doubles as the search box while searching
+            if app.mode == Modes::Search {
+                let search = Paragraph::new(format!("{}▏", app.search_query))
+                    .style(app.config.theme.input)
+                    .block(input_block.title(search_title()));
+                f.render_widget(search, right_chunks[1]);
+            } else {
+                render_input(f, app, wrapped, input_block, right_chunks[1]);
+            }
+
+            if app.mode == Modes::Inputting && !app.suggestions.items.is_empty() {
+                render_suggestions(f, app, right_chunks[1]);
+            }
+
+            // Bottom row is left free by the margin, the status bar spans the panes above it
+            let size = f.size();
+            if size.height > 0 {
+                let status_area = Rect::new(
+                    main_chunks[1].x,
+                    size.height - 1,
+                    main_chunks[1].width + main_chunks[2].width,
+                    1,
                 );
-            f.render_widget(input_block, right_chunks[1]);
+                render_status(f, app, status_area);
+            }
         })
-        .expect("Bad term");
+        .map(|_| ())
+}
+
+/* Render the result of the last action, errors in red, with the push connection's state on the
+ * right
+ * f: Frame from terminal.draw
+ * app: App, kinda given
+ * chunk: Rect to be drawn to
+ */
+fn render_status(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, chunk: Rect) {
+    let (connection, style) = match &app.connection {
+        ConnectionState::Connected => ("● Live".to_string(), app.config.theme.info),
+        ConnectionState::Connecting => ("○ Connecting…".to_string(), app.config.theme.warning),
+        ConnectionState::Reconnecting { attempt, delay } => (
+            format!("○ Reconnecting in {}s (attempt {})", delay, attempt),
+            app.config.theme.warning,
+        ),
+        ConnectionState::Offline(reason) => {
+            (format!("○ Offline: {}", reason), app.config.theme.error)
+        }
+    };
+    let width = (connection.chars().count() as u16).min(chunk.width);
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(0), Constraint::Length(width)].as_ref())
+        .split(chunk);
+    f.render_widget(Paragraph::new(Span::styled(connection, style)), chunks[1]);
+
+    let status = match &app.status {
+        Some(Status::Info(msg)) => Span::styled(msg.clone(), app.config.theme.info),
+        Some(Status::Error(msg)) => Span::styled(format!("Error: {}", msg), app.config.theme.error),
+        None => return,
+    };
+    f.render_widget(Paragraph::new(Spans::from(status)), chunks[0]);
+}
+
+/* Render mention suggestions in a popup just above the input
+ * f: Frame from terminal.draw
+ * app: App, kinda given
+ * input: Rect the input is drawn to
+ */
+fn render_suggestions(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, input: Rect) {
+    let height = (app.suggestions.items.len() as u16 + 2).min(input.y);
+    let width = app
+        .suggestions
+        .items
+        .iter()
+        .map(|name| name.chars().count() as u16 + 4)
+        .max()
+        .unwrap_or(0)
+        .max(20)
+        .min(input.width);
+    let area = Rect::new(input.x, input.y - height, width, height);
+
+    let items: Vec<ListItem> = app
+        .suggestions
+        .items
+        .iter()
+        .map(|name| ListItem::new(name.as_ref()))
+        .collect();
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Mention (Tab)")
+                .border_style(app.config.theme.accent),
+        )
+        .highlight_style(app.config.theme.highlight);
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut app.suggestions.state);
 }
 
 /* Render group display to the given Rect chunk
@@ -127,7 +374,7 @@ fn render_groups(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, chu
         .groups
         .items
         .iter()
-        .map(|i| ListItem::new(i.name.as_ref()))
+        .map(|i| unread_item(&i.name, i.unread, &app.config.theme))
         .collect();
     let group_list = List::new(group_items)
         .block(
@@ -135,21 +382,26 @@ fn render_groups(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, chu
                 .borders(Borders::ALL)
                 .title("(G)roups")
                 .border_style(if app.mode == Modes::GroupNav {
-                    Style::default().fg(Color::Magenta)
+                    app.config.theme.accent
                 } else {
-                    Style::default()
+                    app.config.theme.border
                 }),
         )
-        .style(Style::default().fg(Color::White))
-        .highlight_style(
-            Style::default()
-                .fg(Color::Magenta)
-                .add_modifier(Modifier::BOLD),
-        )
+        .style(app.config.theme.text)
+        .highlight_style(app.config.theme.highlight)
         .highlight_symbol(">");
     f.render_stateful_widget(group_list, chunk, &mut app.groups.state);
 }
 
+// Conversation name, with the unread count standing out when there is anything unread
+fn unread_item(name: &str, unread: u64, theme: &Theme) -> ListItem<'static> {
+    if unread > 0 {
+        ListItem::new(Span::styled(format!("{} ({})", name, unread), theme.unread))
+    } else {
+        ListItem::new(name.to_string())
+    }
+}
+
 /* Render direct message display to the given Rect chunk
  * f: Frame from terminal.draw
  * app: App, kinda given
@@ -160,7 +412,7 @@ fn render_direct(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, chu
         .dms
         .items
         .iter()
-        .map(|i| ListItem::new(i.name.as_ref()))
+        .map(|i| unread_item(&i.name, i.unread, &app.config.theme))
         .collect();
     let dm_list = List::new(dm_items)
         .block(
@@ -168,17 +420,13 @@ fn render_direct(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, chu
                 .borders(Borders::ALL)
                 .title("(D)irect Messages")
                 .border_style(if app.mode == Modes::DirectNav {
-                    Style::default().fg(Color::Magenta)
+                    app.config.theme.accent
                 } else {
-                    Style::default()
+                    app.config.theme.border
                 }),
         )
-        .style(Style::default().fg(Color::White))
-        .highlight_style(
-            Style::default()
-                .fg(Color::Magenta)
-                .add_modifier(Modifier::BOLD),
-        )
+        .style(app.config.theme.text)
+        .highlight_style(app.config.theme.highlight)
         .highlight_symbol(">");
     f.render_stateful_widget(dm_list, chunk, &mut app.dms.state);
 }
@@ -193,13 +441,15 @@ fn render_messages(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, c
         .messages
         .items
         .iter()
-        .enumerate()
-        .map(|(i, m)| {
-            if i % 2 == 0 {
-                ListItem::new(m.display.clone())
-            } else {
-                ListItem::new(m.display.clone())
+        .map(|m| {
+            let mut display = m.display.clone();
+            if m.reply_to.is_some() {
+                // Quote goes between the sender line and the text
+                display
+                    .lines
+                    .insert(1, quote(m.quote.as_deref(), &app.config.theme));
             }
+            ListItem::new(display)
         })
         .collect();
     let msg_list = List::new(message_items)
@@ -208,20 +458,221 @@ fn render_messages(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, c
                 .title("Messages")
                 .borders(Borders::ALL)
                 .border_style(if app.mode == Modes::MessageNav {
-                    Style::default().fg(Color::Magenta)
+                    app.config.theme.accent
                 } else {
-                    Style::default()
+                    app.config.theme.border
                 }),
         )
-        .highlight_style(
-            Style::default()
-                .bg(Color::Black)
-                .add_modifier(Modifier::ITALIC),
-        );
+        .highlight_style(app.config.theme.selected);
     f.render_stateful_widget(msg_list, chunk, &mut app.messages.state);
 }
 
-pub fn draw_term(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut App) {
+/* Render the members of the selected group to the given Rect chunk
+ * f: Frame from terminal.draw
+ * app: App, kinda given
+ * chunk: Rect to be drawn to
+ */
+fn render_members(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, chunk: Rect) {
+    let member_items: Vec<ListItem> = app
+        .member_list
+        .items
+        .iter()
+        .map(|m| {
+            let mut line = vec![
+                Span::styled(
+                    format!("[{:<2}] ", initials(&m.nickname)),
+                    app.config.theme.name,
+                ),
+                Span::raw(m.nickname.clone()),
+            ];
+            let roles: Vec<&str> = m
+                .roles
+                .iter()
+                .filter(|r| *r != "user")
+                .map(|r| r.as_ref())
+                .collect();
+            if !roles.is_empty() {
+                line.push(Span::styled(
+                    format!("  {}", roles.join(", ")),
+                    app.config.theme.warning,
+                ));
+            }
+            if m.muted {
+                line.push(Span::styled("  (muted)", app.config.theme.dim));
+            }
+            ListItem::new(Spans::from(line))
+        })
+        .collect();
+    let group_name = app
+        .groups
+        .state
+        .selected()
+        .map(|i| app.groups.items[i].name.clone())
+        .unwrap_or_default();
+    let member_list = List::new(member_items)
+        .block(
+            Block::default()
+                .title(format!(
+                    "Members of {} ({})",
+                    group_name,
+                    app.member_list.items.len()
+                ))
+                .borders(Borders::ALL)
+                .border_style(app.config.theme.accent),
+        )
+        .highlight_style(app.config.theme.selected);
+    f.render_stateful_widget(member_list, chunk, &mut app.member_list.state);
+}
+
+/* Render search results to the given Rect chunk
+ * f: Frame from terminal.draw
+ * app: App, kinda given
+ * chunk: Rect to be drawn to
+ */
+fn render_search(f: &mut Frame<CrosstermBackend<io::Stdout>>, app: &mut App, chunk: Rect) {
+    // Terms of the query the results are for, not whatever has been typed since
+    let terms = Query::parse(&app.last_search)
+        .map(|query| query.terms)
+        .unwrap_or_default();
+    let width = chunk.width.saturating_sub(4).max(10) as usize;
+    let hit_items: Vec<ListItem> = app
+        .search_results
+        .items
+        .iter()
+        .map(|hit| ListItem::new(search_hit(hit, &terms, width, &app.config.theme)))
+        .collect();
+    let hit_list = List::new(hit_items)
+        .block(
+            Block::default()
+                .title(format!(
+                    "Search Results ({})",
+                    app.search_results.items.len()
+                ))
+                .borders(Borders::ALL)
+                .border_style(app.config.theme.accent),
+        )
+        .highlight_style(app.config.theme.selected);
+    f.render_stateful_widget(hit_list, chunk, &mut app.search_results.state);
+}
+
+// Where and when the hit was sent, the message before it, then the hit with matches highlighted
+fn search_hit(
+    hit: &SearchHit,
+    terms: &[String],
+    width: usize,
+    theme: &Theme,
+) -> Vec<Spans<'static>> {
+    let sent = Local.timestamp(hit.message.created_at, 0);
+    let mut lines = vec![Spans::from(Span::styled(
+        format!(
+            "{} - {} - {}",
+            hit.conversation_name,
+            hit.message.name,
+            sent.format("%Y-%m-%d %H:%M")
+        ),
+        theme.name,
+    ))];
+    if let Some(previous) = &hit.previous {
+        lines.push(Spans::from(Span::styled(
+            format!("  ┃ {}", quote_of(previous)),
+            theme.dim,
+        )));
+    }
+    let text = fill(hit.message.text.as_deref().unwrap_or(""), width);
+    for line in text.lines() {
+        let mut spans = vec![Span::raw("  ")];
+        let mut last = 0;
+        for (start, end) in highlights(line, terms) {
+            spans.push(Span::raw(line[last..start].to_string()));
+            spans.push(Span::styled(line[start..end].to_string(), theme.mention));
+            last = end;
+        }
+        spans.push(Span::raw(line[last..].to_string()));
+        lines.push(Spans::from(spans));
+    }
+    lines
+}
+
+fn search_title() -> String {
+    "Search (words, \"phrases\", from:name, after:YYYY-MM-DD, before:YYYY-MM-DD, Esc to close)"
+        .to_string()
+}
+
+// Up to two initials standing in for a member's avatar
+fn initials(name: &str) -> String {
+    name.split_whitespace()
+        .filter_map(|word| word.chars().next())
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+// Line quoting the message being replied to
+fn quote(excerpt: Option<&str>, theme: &Theme) -> Spans<'static> {
+    let excerpt = excerpt.unwrap_or("Original message not found");
+    Spans::from(Span::styled(format!("  ┃ {}", excerpt), theme.dim))
+}
+
+/* Render what's being written, scrolled to keep the cursor in view, with the terminal's cursor
+ * placed in it while writing
+ * f: Frame from terminal.draw
+ * app: App, kinda given
+ * wrapped: Composer's text wrapped to the inside of the box
+ * block: Box drawn around it
+ * chunk: Rect to be drawn to
+ */
+fn render_input(
+    f: &mut Frame<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+    wrapped: Wrapped,
+    block: Block,
+    chunk: Rect,
+) {
+    let block = block.title(input_title(app));
+    let inner = block.inner(chunk);
+    let (column, row) = wrapped.cursor;
+    let scroll = (row + 1).saturating_sub(inner.height);
+    let lines: Vec<Spans> = wrapped.rows.into_iter().map(Spans::from).collect();
+    let input = Paragraph::new(lines)
+        .style(app.config.theme.input)
+        .block(block)
+        .scroll((scroll, 0));
+    f.render_widget(input, chunk);
+    if app.mode == Modes::Inputting && inner.height > 0 {
+        f.set_cursor(inner.x + column.min(inner.width), inner.y + row - scroll);
+    }
+}
+
+/* Height of the message box, the size the layout gives it or enough for every line up to half of
+ * the pane
+ * layout: Sizes of the panes
+ * pane: Height of the messages pane the box is part of
+ * lines: Lines being written
+ */
+fn input_height(layout: &LayoutConfig, pane: u16, lines: u16) -> u16 {
+    let base = (pane * layout.input / 100).max(3);
+    (lines + 2).clamp(base, base.max(pane / 2))
+}
+
+fn input_title(app: &App) -> String {
+    let replying_to = app
+        .reply_to
+        .as_ref()
+        .and_then(|id| app.messages.items.iter().find(|m| &m.id == id));
+    let mut title = match replying_to {
+        Some(msg) => format!("Reply to {} (Esc to cancel)", msg.name),
+        None => "New Message".to_string(),
+    };
+    if let Some(name) = app.pending_image.as_ref().and_then(|p| p.file_name()) {
+        title.push_str(&format!(" [{}]", name.to_string_lossy()));
+    }
+    title
+}
+
+pub fn draw_term(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+) -> io::Result<()> {
     match app.disp {
         DispMode::Startup => draw_startup(terminal, app),
         DispMode::Main => draw_main(terminal, app),
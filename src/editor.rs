@@ -0,0 +1,74 @@
+use crate::error::{AppError, Result};
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+#[cfg(windows)]
+static DEFAULT_EDITOR: &str = "notepad";
+
+#[cfg(not(windows))]
+static DEFAULT_EDITOR: &str = "vi";
+
+/* Let the user edit a draft in $VISUAL or $EDITOR, returning what they saved. The terminal has
+ * to be handed over to the editor first, out of raw mode and the alternate screen
+ * draft: Text to start from
+ */
+pub fn edit(draft: &str) -> Result<String> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+    // Editors are often given with arguments, e.g. "code --wait"
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+
+    let (path, mut file) = draft_file()?;
+    let written = file.write_all(draft.as_bytes());
+    drop(file);
+    if let Err(e) = written {
+        let _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+    let status = Command::new(program).args(words).arg(&path).status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    let status = status.map_err(|e| format!("Couldn't run {}: {}", program, e))?;
+    if !status.success() {
+        return Err(AppError::Invalid(format!(
+            "{} exited with {}, the draft is unchanged",
+            program, status
+        )));
+    }
+    // Editors end the file with a newline, which isn't part of the message
+    Ok(edited?.trim_end_matches(&['\n', '\r'][..]).to_string())
+}
+
+/* Create the temporary file the draft is edited in. It's always a new file only the user can
+ * read, so nothing already at that path, like a symlink someone else left there, gets written to
+ */
+fn draft_file() -> Result<(PathBuf, File)> {
+    for attempt in 0..100 {
+        let mut path = env::temp_dir();
+        path.push(format!("gmtui-draft-{}-{}.txt", process::id(), attempt));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(AppError::Invalid(
+        "Couldn't find a free name for the draft file".to_string(),
+    ))
+}
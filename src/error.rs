@@ -0,0 +1,82 @@
+use crate::groupme::GroupMeError;
+
+use std::{error, fmt, io};
+
+pub type Result<T> = std::result::Result<T, AppError>;
+
+// Anything that can go wrong outside of drawing, shown to the user in the status bar
+#[derive(Debug)]
+pub enum AppError {
+    GroupMe(GroupMeError),
+    // Reading or writing local files (config, cache, images, exports)
+    Io(io::Error),
+    // config.toml is missing something or couldn't be parsed
+    Config(String),
+    // Request that can't be carried out as asked, e.g. an unknown group name
+    Invalid(String),
+}
+
+impl AppError {
+    // Whether trying again later might work, e.g. a dropped connection
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AppError::GroupMe(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::GroupMe(e) if e.status() == Some(401) => write!(
+                f,
+                "GroupMe rejected the access token, check secret in config.toml"
+            ),
+            AppError::GroupMe(e) => write!(f, "{}", e),
+            AppError::Io(e) => write!(f, "{}", e),
+            AppError::Config(msg) => write!(f, "Invalid config: {}", msg),
+            AppError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for AppError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            AppError::GroupMe(e) => Some(e),
+            AppError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<GroupMeError> for AppError {
+    fn from(e: GroupMeError) -> Self {
+        AppError::GroupMe(e)
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(e: io::Error) -> Self {
+        AppError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::Io(e.into())
+    }
+}
+
+impl From<String> for AppError {
+    fn from(msg: String) -> Self {
+        AppError::Invalid(msg)
+    }
+}
+
+impl From<&str> for AppError {
+    fn from(msg: &str) -> Self {
+        AppError::Invalid(msg.to_string())
+    }
+}
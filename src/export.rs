@@ -0,0 +1,223 @@
+use crate::api::attachment_line;
+use crate::groupme::{Conversation, GroupMeClient, Message, MessageQuery};
+
+use crate::error::Result;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use chrono::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // One message per line, exactly as the API returned it
+    Json,
+    Markdown,
+    // Single page with the styles inlined, so it can be opened without anything else
+    Html,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_ref() {
+            "json" | "jsonl" => Some(Format::Json),
+            "md" | "markdown" => Some(Format::Markdown),
+            "html" | "htm" => Some(Format::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "jsonl",
+            Format::Markdown => "md",
+            Format::Html => "html",
+        }
+    }
+}
+
+/* Page through the entire history of a conversation, oldest first
+ * client: GroupMe api client
+ * conv: Conversation to fetch
+ */
+pub fn fetch_history(client: &GroupMeClient, conv: &Conversation) -> Result<Vec<Message>> {
+    let mut msgs: Vec<Message> = Vec::new();
+    loop {
+        let query = MessageQuery {
+            before_id: msgs.last().map(|msg| msg.id.clone()),
+            limit: Some(100),
+            ..Default::default()
+        };
+        let page = client.messages(conv, &query)?;
+        // Stop if the server keeps handing back the same page rather than looping forever
+        if page.is_empty() || page.last().map(|msg| &msg.id) == query.before_id.as_ref() {
+            break;
+        }
+        msgs.extend(page);
+    }
+    msgs.reverse();
+    Ok(msgs)
+}
+
+/* Fetch a conversation's history and write it to a file, returning how many messages were
+ * written
+ * client: GroupMe api client
+ * conv: Conversation to export
+ * title: Name of the conversation, used as the heading
+ * format: Format to write
+ * path: File to write, replaced if it exists
+ */
+pub fn export(
+    client: &GroupMeClient,
+    conv: &Conversation,
+    title: &str,
+    format: Format,
+    path: &Path,
+) -> Result<usize> {
+    let msgs = fetch_history(client, conv)?;
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out, format, title, &msgs)?;
+    out.flush()?;
+    Ok(msgs.len())
+}
+
+/* Write messages in the given format
+ * out: Where to write to
+ * format: Format to write
+ * title: Name of the conversation, used as the heading
+ * msgs: Messages, oldest first
+ */
+pub fn write<W: Write>(
+    out: &mut W,
+    format: Format,
+    title: &str,
+    msgs: &[Message],
+) -> io::Result<()> {
+    match format {
+        Format::Json => write_json(out, msgs),
+        Format::Markdown => write_markdown(out, title, msgs),
+        Format::Html => write_html(out, title, msgs),
+    }
+}
+
+fn write_json<W: Write>(out: &mut W, msgs: &[Message]) -> io::Result<()> {
+    for msg in msgs {
+        serde_json::to_writer(&mut *out, msg)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn write_markdown<W: Write>(out: &mut W, title: &str, msgs: &[Message]) -> io::Result<()> {
+    writeln!(out, "# {}", title)?;
+    for msg in msgs {
+        writeln!(out)?;
+        writeln!(out, "### {} - {}{}", msg.name, timestamp(msg), likes(msg))?;
+        let text = msg.text.as_deref().unwrap_or("");
+        if !text.is_empty() {
+            writeln!(out)?;
+            // Trailing double space keeps the message's own line breaks
+            writeln!(out, "{}", text.lines().collect::<Vec<_>>().join("  \n"))?;
+        }
+        let attachments = attachments(msg);
+        if !attachments.is_empty() {
+            writeln!(out)?;
+            for attachment in attachments {
+                writeln!(out, "- {}", attachment)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:48em;margin:2em auto;padding:0 1em;\
+color:#222}.message{border-bottom:1px solid #ddd;padding:.6em 0}.meta{color:#666;font-size:.9em}\
+.name{font-weight:bold;color:#2a5db0}.likes{color:#c0392b}p{margin:.3em 0;white-space:pre-wrap}\
+ul{margin:.3em 0;color:#16768a}";
+
+fn write_html<W: Write>(out: &mut W, title: &str, msgs: &[Message]) -> io::Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape(title))?;
+    writeln!(out, "<style>{}</style>", STYLE)?;
+    writeln!(out, "</head><body>")?;
+    writeln!(out, "<h1>{}</h1>", escape(title))?;
+    for msg in msgs {
+        let sent = Local.timestamp(msg.created_at, 0);
+        writeln!(out, "<div class=\"message\" id=\"{}\">", escape(&msg.id))?;
+        writeln!(
+            out,
+            "<div class=\"meta\"><span class=\"name\">{}</span> <time datetime=\"{}\">{}</time> <span class=\"likes\">{}</span></div>",
+            escape(&msg.name),
+            sent.to_rfc3339(),
+            timestamp(msg),
+            escape(likes(msg).trim_start_matches(" - "))
+        )?;
+        let text = msg.text.as_deref().unwrap_or("");
+        if !text.is_empty() {
+            writeln!(out, "<p>{}</p>", escape(text))?;
+        }
+        let attachments = attachments(msg);
+        if !attachments.is_empty() {
+            writeln!(out, "<ul>")?;
+            for attachment in attachments {
+                if attachment.starts_with("http") {
+                    let url = escape(&attachment);
+                    writeln!(out, "<li><a href=\"{}\">{}</a></li>", url, url)?;
+                } else {
+                    writeln!(out, "<li>{}</li>", escape(&attachment))?;
+                }
+            }
+            writeln!(out, "</ul>")?;
+        }
+        writeln!(out, "</div>")?;
+    }
+    writeln!(out, "</body></html>")
+}
+
+fn timestamp(msg: &Message) -> String {
+    Local
+        .timestamp(msg.created_at, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn likes(msg: &Message) -> String {
+    match msg.favorited_by.len() {
+        0 => String::new(),
+        1 => " - 1 like".to_string(),
+        n => format!(" - {} likes", n),
+    }
+}
+
+fn attachments(msg: &Message) -> Vec<String> {
+    msg.attachments
+        .iter()
+        .filter_map(|attachment| attachment_line(attachment, msg.event.as_ref()))
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/* File name to export to when none is given, e.g. "Book_Club.md"
+ * title: Name of the conversation
+ * format: Format being written
+ */
+pub fn default_file_name(title: &str, format: Format) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.{}", name, format.extension())
+}
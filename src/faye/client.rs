@@ -0,0 +1,621 @@
+use crate::faye::error::{FayeError, Result};
+use crate::faye::transport::Transport;
+
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+// Longest wait for the reply to a request, and how long past the advised timeout to wait for a
+// connect's reply before treating the connection as dead
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long the server holds a connect open when it hasn't advised otherwise
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Reconnect advice sent by the server with handshake and connect replies
+#[derive(Debug, Clone, PartialEq)]
+pub enum Advice {
+    // Connect again after waiting the interval, in milliseconds
+    Retry(u64),
+    // Client id is no longer valid, start over with a handshake
+    Handshake,
+    // Don't reconnect at all
+    None,
+}
+
+impl Advice {
+    /* Work out what the server wants done after a reply. Bayeux defaults to retrying straight
+     * away, or handshaking again when the request wasn't successful
+     * reply: Reply to a handshake or connect
+     */
+    pub fn from_reply(reply: &Value) -> Advice {
+        let advice = &reply["advice"];
+        let interval = advice["interval"].as_u64().unwrap_or(0);
+        match advice["reconnect"].as_str() {
+            Some("retry") => Advice::Retry(interval),
+            Some("handshake") => Advice::Handshake,
+            Some("none") => Advice::None,
+            _ if reply["successful"].as_bool() == Some(false) => Advice::Handshake,
+            _ => Advice::Retry(interval),
+        }
+    }
+}
+
+// Message published to a channel we're subscribed to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Published {
+    pub channel: String,
+    pub data: Value,
+}
+
+// Outcome of one /meta/connect poll
+#[derive(Debug)]
+pub struct ConnectReply {
+    pub advice: Advice,
+    pub messages: Vec<Published>,
+}
+
+/* Bayeux client over any Transport. Requests are made one at a time, each waiting for its
+ * reply, and published messages that arrive in the meantime are held until the next connect.
+ * A connect can also be left outstanding with start_connect, so other requests can be made
+ * while waiting on it with poll
+ */
+pub struct FayeClient<T: Transport> {
+    transport: T,
+    client_id: Option<String>,
+    next_id: u64,
+    subscriptions: Vec<String>,
+    pending: Vec<Published>,
+    // Reply to an outstanding connect that arrived while waiting on another request
+    connect_reply: Option<Value>,
+    // When the outstanding connect was sent
+    connect_sent: Option<Instant>,
+    // How long the server says it may hold a connect before replying
+    connect_timeout: Duration,
+}
+
+impl<T: Transport> FayeClient<T> {
+    pub fn new(transport: T) -> FayeClient<T> {
+        FayeClient {
+            transport,
+            client_id: None,
+            next_id: 1,
+            subscriptions: Vec::new(),
+            pending: Vec::new(),
+            connect_reply: None,
+            connect_sent: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    // Channels subscribed to since the last handshake
+    pub fn subscriptions(&self) -> &[String] {
+        &self.subscriptions
+    }
+
+    /* Get a new client id. Subscriptions belong to the old one, so they have to be made again */
+    pub fn handshake(&mut self) -> Result<Advice> {
+        self.client_id = None;
+        self.subscriptions.clear();
+        self.connect_reply = None;
+        self.connect_sent = None;
+        let reply = self.request(json!({
+            "channel": "/meta/handshake",
+            "version": "1.0",
+            "supportedConnectionTypes": ["websocket"],
+        }))?;
+        let client_id = reply["clientId"]
+            .as_str()
+            .ok_or_else(|| FayeError::Protocol("handshake reply has no clientId".to_string()))?;
+        self.client_id = Some(client_id.to_string());
+        self.advise_timeout(&reply);
+        Ok(Advice::from_reply(&reply))
+    }
+
+    /* Start receiving messages published to a channel
+     * channel: Channel to subscribe to, e.g. /user/<id>
+     * ext: Extension data, such as credentials, sent along with the request
+     */
+    pub fn subscribe(&mut self, channel: &str, ext: Option<Value>) -> Result<()> {
+        let msg = json!({
+            "channel": "/meta/subscribe",
+            "clientId": self.require_client_id()?,
+            "subscription": channel,
+        });
+        self.request(with_ext(msg, ext))?;
+        if !self.subscriptions.iter().any(|c| c == channel) {
+            self.subscriptions.push(channel.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn unsubscribe(&mut self, channel: &str, ext: Option<Value>) -> Result<()> {
+        let msg = json!({
+            "channel": "/meta/unsubscribe",
+            "clientId": self.require_client_id()?,
+            "subscription": channel,
+        });
+        self.request(with_ext(msg, ext))?;
+        self.subscriptions.retain(|c| c != channel);
+        Ok(())
+    }
+
+    /* Publish data to a channel
+     * channel: Channel to publish to
+     * data: Message to publish
+     * ext: Extension data, such as credentials, sent along with the message
+     */
+    pub fn publish(&mut self, channel: &str, data: Value, ext: Option<Value>) -> Result<()> {
+        let msg = json!({
+            "channel": channel,
+            "clientId": self.require_client_id()?,
+            "data": data,
+        });
+        self.request(with_ext(msg, ext))?;
+        Ok(())
+    }
+
+    /* Poll for published messages, blocking until the server replies. An unsuccessful connect
+     * isn't an error, the advice says what to do about it
+     */
+    pub fn connect(&mut self) -> Result<ConnectReply> {
+        let wait = self.connect_timeout + REPLY_TIMEOUT;
+        let reply = self.exchange(self.connect_message()?, wait)?;
+        self.advise_timeout(&reply);
+        Ok(ConnectReply {
+            advice: Advice::from_reply(&reply),
+            messages: self.take_published(),
+        })
+    }
+
+    /* Send a connect without waiting for the reply, which poll picks up */
+    pub fn start_connect(&mut self) -> Result<()> {
+        let msg = self.connect_message()?;
+        self.send(msg)?;
+        self.connect_sent = Some(Instant::now());
+        Ok(())
+    }
+
+    /* Wait up to timeout for the reply to the connect sent by start_connect, returning its
+     * advice once it arrives. Published messages received meanwhile go to take_published.
+     * Fails with TimedOut once the reply is overdue, as the connection has most likely died
+     * timeout: Longest time to wait
+     */
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<Advice>> {
+        if self.connect_reply.is_none() {
+            let mut timeout = timeout;
+            if let Some(sent) = self.connect_sent {
+                let overdue = sent + self.connect_timeout + REPLY_TIMEOUT;
+                let left = overdue.saturating_duration_since(Instant::now());
+                if left == Duration::from_secs(0) {
+                    return Err(FayeError::TimedOut);
+                }
+                timeout = timeout.min(left);
+            }
+            if let Some(batch) = self.transport.receive_timeout(timeout)? {
+                self.sort(batch, None);
+            }
+        }
+        let reply = match self.connect_reply.take() {
+            Some(reply) => reply,
+            None => return Ok(None),
+        };
+        self.connect_sent = None;
+        self.advise_timeout(&reply);
+        Ok(Some(Advice::from_reply(&reply)))
+    }
+
+    // Published messages received since this was last called
+    pub fn take_published(&mut self) -> Vec<Published> {
+        self.pending.drain(..).collect()
+    }
+
+    /* Tell the server we're leaving and close the transport, ignoring any errors */
+    pub fn disconnect(&mut self) {
+        if let Some(client_id) = self.client_id.take() {
+            let _ = self.transport.send(vec![json!({
+                "channel": "/meta/disconnect",
+                "clientId": client_id,
+                "id": self.next_id.to_string(),
+            })]);
+        }
+        self.transport.close();
+    }
+
+    fn connect_message(&self) -> Result<Value> {
+        Ok(json!({
+            "channel": "/meta/connect",
+            "clientId": self.require_client_id()?,
+            "connectionType": "websocket",
+        }))
+    }
+
+    // Keep the timeout the server advised, if a reply has one
+    fn advise_timeout(&mut self, reply: &Value) {
+        if let Some(timeout) = reply["advice"]["timeout"].as_u64() {
+            self.connect_timeout = Duration::from_millis(timeout);
+        }
+    }
+
+    fn require_client_id(&self) -> Result<String> {
+        self.client_id.clone().ok_or(FayeError::NotHandshaken)
+    }
+
+    // Send a message and wait for its reply, failing if it wasn't successful
+    fn request(&mut self, msg: Value) -> Result<Value> {
+        let reply = self.exchange(msg, REPLY_TIMEOUT)?;
+        if reply["successful"].as_bool() == Some(false) {
+            let channel = reply["subscription"]
+                .as_str()
+                .or_else(|| reply["channel"].as_str())
+                .unwrap_or("request");
+            return Err(FayeError::Refused {
+                channel: channel.to_string(),
+                error: reply["error"]
+                    .as_str()
+                    .unwrap_or("no reason given")
+                    .to_string(),
+            });
+        }
+        Ok(reply)
+    }
+
+    /* Send a message and wait for its reply, holding on to anything published in the meantime
+     * msg: Message to send
+     * wait: Longest time to wait for the reply before failing with TimedOut
+     */
+    fn exchange(&mut self, msg: Value, wait: Duration) -> Result<Value> {
+        let channel = msg["channel"].as_str().unwrap_or_default().to_string();
+        let id = self.send(msg)?;
+        let deadline = Instant::now() + wait;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return Err(FayeError::TimedOut);
+            }
+            let batch = match self.transport.receive_timeout(left)? {
+                Some(batch) => batch,
+                None => return Err(FayeError::TimedOut),
+            };
+            if let Some(reply) = self.sort(batch, Some((&channel, &id))) {
+                return Ok(reply);
+            }
+        }
+    }
+
+    // Give a message the next id and send it, returning the id
+    fn send(&mut self, mut msg: Value) -> Result<String> {
+        let id = self.next_id.to_string();
+        self.next_id += 1;
+        msg["id"] = json!(id);
+        self.transport.send(vec![msg])?;
+        Ok(id)
+    }
+
+    /* Pick the reply being waited on out of a batch, holding published messages and a reply to
+     * an outstanding connect for later
+     * batch: Messages received from the transport
+     * waiting: Channel and id of the request being waited on
+     */
+    fn sort(&mut self, batch: Vec<Value>, waiting: Option<(&str, &str)>) -> Option<Value> {
+        let mut reply = None;
+        for message in batch {
+            let channel = message["channel"].as_str().unwrap_or_default().to_string();
+            // Replies carry successful, published messages carry data
+            let is_reply = !message["successful"].is_null();
+            let ours = waiting.is_some_and(|(waiting_channel, id)| {
+                channel == waiting_channel && (message["id"].is_null() || message["id"] == id)
+            });
+            if is_reply && ours && reply.is_none() {
+                reply = Some(message);
+            } else if is_reply && channel == "/meta/connect" {
+                self.connect_reply = Some(message);
+            } else if !is_reply && !message["data"].is_null() {
+                self.pending.push(Published {
+                    channel,
+                    data: message["data"].clone(),
+                });
+            }
+            // Anything else is a late reply to an earlier request, which has nothing to add
+        }
+        reply
+    }
+}
+
+fn with_ext(mut msg: Value, ext: Option<Value>) -> Value {
+    if let Some(ext) = ext {
+        msg["ext"] = ext;
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    // Transport that records what's sent and hands back scripted batches in order
+    #[derive(Default)]
+    struct MemoryTransport {
+        sent: Vec<Value>,
+        incoming: VecDeque<Vec<Value>>,
+    }
+
+    impl Transport for MemoryTransport {
+        fn send(&mut self, messages: Vec<Value>) -> Result<()> {
+            self.sent.extend(messages);
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Vec<Value>> {
+            self.incoming.pop_front().ok_or(FayeError::Closed)
+        }
+
+        fn receive_timeout(&mut self, _timeout: Duration) -> Result<Option<Vec<Value>>> {
+            Ok(self.incoming.pop_front())
+        }
+
+        fn close(&mut self) {}
+    }
+
+    /* Client with the given batches waiting to be received
+     * batches: Batches the server sends, in order
+     */
+    fn client(batches: Vec<Vec<Value>>) -> FayeClient<MemoryTransport> {
+        FayeClient::new(MemoryTransport {
+            sent: Vec::new(),
+            incoming: batches.into(),
+        })
+    }
+
+    fn handshake_reply() -> Value {
+        json!({
+            "channel": "/meta/handshake",
+            "id": "1",
+            "successful": true,
+            "clientId": "abc",
+            "advice": {"reconnect": "retry", "interval": 0, "timeout": 30000},
+        })
+    }
+
+    fn reply(channel: &str, id: u64) -> Value {
+        json!({"channel": channel, "id": id.to_string(), "successful": true})
+    }
+
+    fn published(channel: &str, text: &str) -> Value {
+        json!({"channel": channel, "data": {"text": text}})
+    }
+
+    #[test]
+    fn subscriptions_are_kept_until_the_next_handshake() {
+        let mut client = client(vec![
+            vec![handshake_reply()],
+            vec![reply("/meta/subscribe", 2)],
+            vec![reply("/meta/subscribe", 3)],
+            vec![reply("/meta/subscribe", 4)],
+            vec![reply("/meta/unsubscribe", 5)],
+            vec![json!({
+                "channel": "/meta/handshake",
+                "id": "6",
+                "successful": true,
+                "clientId": "def",
+            })],
+        ]);
+        assert_eq!(client.handshake().unwrap(), Advice::Retry(0));
+        assert_eq!(client.client_id(), Some("abc"));
+
+        client.subscribe("/user/1", None).unwrap();
+        client.subscribe("/group/2", None).unwrap();
+        client.subscribe("/group/2", None).unwrap();
+        assert_eq!(client.subscriptions(), ["/user/1", "/group/2"]);
+        client.unsubscribe("/group/2", None).unwrap();
+        assert_eq!(client.subscriptions(), ["/user/1"]);
+
+        let sent = &client.transport.sent;
+        assert_eq!(sent[1]["clientId"], "abc");
+        assert_eq!(sent[1]["subscription"], "/user/1");
+        assert_eq!(sent[4]["channel"], "/meta/unsubscribe");
+
+        client.handshake().unwrap();
+        assert_eq!(client.client_id(), Some("def"));
+        assert!(client.subscriptions().is_empty());
+    }
+
+    #[test]
+    fn refused_subscribe_is_an_error_and_not_kept() {
+        let mut client = client(vec![
+            vec![handshake_reply()],
+            vec![json!({
+                "channel": "/meta/subscribe",
+                "id": "2",
+                "successful": false,
+                "subscription": "/group/2",
+                "error": "401::Unauthorized",
+            })],
+        ]);
+        client.handshake().unwrap();
+        match client.subscribe("/group/2", None) {
+            Err(FayeError::Refused { channel, error }) => {
+                assert_eq!(channel, "/group/2");
+                assert_eq!(error, "401::Unauthorized");
+            }
+            result => panic!("expected Refused, got {:?}", result),
+        }
+        assert!(client.subscriptions().is_empty());
+    }
+
+    #[test]
+    fn requests_need_a_handshake() {
+        let mut client = client(Vec::new());
+        assert!(matches!(
+            client.subscribe("/user/1", None),
+            Err(FayeError::NotHandshaken)
+        ));
+        assert!(matches!(
+            client.start_connect(),
+            Err(FayeError::NotHandshaken)
+        ));
+        assert!(client.transport.sent.is_empty());
+    }
+
+    #[test]
+    fn published_messages_are_held_while_waiting_on_a_reply() {
+        let mut client = client(vec![
+            vec![handshake_reply()],
+            vec![published("/user/1", "first")],
+            vec![
+                published("/user/1", "second"),
+                reply("/meta/subscribe", 2),
+                published("/group/2", "third"),
+            ],
+        ]);
+        client.handshake().unwrap();
+        client.subscribe("/group/2", None).unwrap();
+
+        let texts: Vec<_> = client
+            .take_published()
+            .into_iter()
+            .map(|p| (p.channel, p.data["text"].clone()))
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                ("/user/1".to_string(), json!("first")),
+                ("/user/1".to_string(), json!("second")),
+                ("/group/2".to_string(), json!("third")),
+            ]
+        );
+        assert!(client.take_published().is_empty());
+    }
+
+    #[test]
+    fn late_replies_to_earlier_requests_are_ignored() {
+        let mut client = client(vec![
+            vec![handshake_reply()],
+            vec![reply("/meta/subscribe", 2)],
+            vec![reply("/meta/subscribe", 2), reply("/meta/subscribe", 3)],
+        ]);
+        client.handshake().unwrap();
+        client.subscribe("/user/1", None).unwrap();
+        client.subscribe("/group/2", None).unwrap();
+        // The subscribe to /group/2 took its own reply, leaving nothing behind
+        assert!(client.transport.incoming.is_empty());
+        assert!(client.take_published().is_empty());
+    }
+
+    #[test]
+    fn connect_reply_during_another_request_is_kept_for_poll() {
+        let mut client = client(vec![
+            vec![handshake_reply()],
+            vec![
+                json!({
+                    "channel": "/meta/connect",
+                    "id": "2",
+                    "successful": true,
+                    "advice": {"reconnect": "retry", "interval": 500},
+                }),
+                published("/user/1", "hello"),
+                reply("/meta/subscribe", 3),
+            ],
+        ]);
+        client.handshake().unwrap();
+        client.start_connect().unwrap();
+        client.subscribe("/group/2", None).unwrap();
+
+        assert_eq!(
+            client.poll(Duration::from_millis(1)).unwrap(),
+            Some(Advice::Retry(500))
+        );
+        assert_eq!(client.take_published().len(), 1);
+        // The reply was used up, so the next poll waits on a new one
+        assert_eq!(client.poll(Duration::from_millis(1)).unwrap(), None);
+    }
+
+    #[test]
+    fn poll_returns_the_connect_reply_once_it_arrives() {
+        let mut client = client(vec![
+            vec![handshake_reply()],
+            vec![published("/user/1", "hello")],
+            vec![json!({
+                "channel": "/meta/connect",
+                "id": "2",
+                "successful": false,
+                "advice": {"reconnect": "handshake"},
+            })],
+        ]);
+        client.handshake().unwrap();
+        client.start_connect().unwrap();
+
+        assert_eq!(client.poll(Duration::from_millis(1)).unwrap(), None);
+        assert_eq!(client.take_published().len(), 1);
+        assert_eq!(
+            client.poll(Duration::from_millis(1)).unwrap(),
+            Some(Advice::Handshake)
+        );
+    }
+
+    #[test]
+    fn overdue_connect_reply_times_out() {
+        let mut client = client(vec![vec![json!({
+            "channel": "/meta/handshake",
+            "id": "1",
+            "successful": true,
+            "clientId": "abc",
+            "advice": {"timeout": 0},
+        })]]);
+        client.handshake().unwrap();
+        client.start_connect().unwrap();
+        client.connect_sent = Some(Instant::now() - REPLY_TIMEOUT);
+        assert!(matches!(
+            client.poll(Duration::from_millis(1)),
+            Err(FayeError::TimedOut)
+        ));
+    }
+
+    #[test]
+    fn request_without_a_reply_times_out() {
+        let mut client = client(vec![vec![handshake_reply()]]);
+        client.handshake().unwrap();
+        assert!(matches!(
+            client.subscribe("/user/1", None),
+            Err(FayeError::TimedOut)
+        ));
+    }
+
+    #[test]
+    fn advice_defaults() {
+        assert_eq!(Advice::from_reply(&json!({})), Advice::Retry(0));
+        assert_eq!(
+            Advice::from_reply(&json!({"successful": true})),
+            Advice::Retry(0)
+        );
+        assert_eq!(
+            Advice::from_reply(&json!({"successful": false})),
+            Advice::Handshake
+        );
+        assert_eq!(
+            Advice::from_reply(&json!({"advice": {"interval": 1000}})),
+            Advice::Retry(1000)
+        );
+        assert_eq!(
+            Advice::from_reply(&json!({
+                "successful": false,
+                "advice": {"reconnect": "retry", "interval": 250},
+            })),
+            Advice::Retry(250)
+        );
+        assert_eq!(
+            Advice::from_reply(&json!({"advice": {"reconnect": "none"}})),
+            Advice::None
+        );
+        assert_eq!(
+            Advice::from_reply(&json!({"advice": {"reconnect": "handshake"}})),
+            Advice::Handshake
+        );
+    }
+}
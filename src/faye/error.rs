@@ -0,0 +1,42 @@
+use std::{error, fmt};
+
+pub type Result<T> = std::result::Result<T, FayeError>;
+
+#[derive(Debug)]
+pub enum FayeError {
+    // Sending or receiving failed, or the connection dropped
+    Transport(String),
+    // Server closed the connection
+    Closed,
+    // Nothing was received before the read timeout
+    TimedOut,
+    // Server answered a meta message with successful set to false
+    Refused { channel: String, error: String },
+    // Something that doesn't follow the protocol, e.g. a reply that isn't JSON
+    Protocol(String),
+    // Operation needs a client id, but there hasn't been a successful handshake
+    NotHandshaken,
+}
+
+impl fmt::Display for FayeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FayeError::Transport(msg) => write!(f, "Push connection failed: {}", msg),
+            FayeError::Closed => write!(f, "Push server closed the connection"),
+            FayeError::TimedOut => write!(f, "Push server didn't reply in time"),
+            FayeError::Refused { channel, error } => {
+                write!(f, "Push server refused {}: {}", channel, error)
+            }
+            FayeError::Protocol(msg) => write!(f, "Unexpected reply from push server: {}", msg),
+            FayeError::NotHandshaken => write!(f, "Not connected to the push server"),
+        }
+    }
+}
+
+impl error::Error for FayeError {}
+
+impl From<serde_json::Error> for FayeError {
+    fn from(e: serde_json::Error) -> Self {
+        FayeError::Protocol(e.to_string())
+    }
+}
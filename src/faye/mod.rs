@@ -0,0 +1,9 @@
+// Client for the Bayeux protocol as spoken by Faye, which GroupMe uses to push messages. It only
+// talks to a Transport, so it can be driven by something other than a real websocket.
+pub mod client;
+pub mod error;
+pub mod transport;
+
+pub use client::{Advice, ConnectReply, FayeClient, Published};
+pub use error::{FayeError, Result};
+pub use transport::{Transport, WebSocketTransport};
@@ -0,0 +1,171 @@
+use crate::faye::error::{FayeError, Result};
+
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use native_tls::TlsConnector;
+
+use serde_json::Value;
+
+use tungstenite::{
+    client, client::AutoStream, handshake::HandshakeError, protocol::WebSocket, stream::Stream,
+    Message,
+};
+
+use url::Url;
+
+// Longest wait to open the connection, and for each read or write while setting it up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/* Carries Bayeux messages to and from the server. Every send and receive is a batch, as the
+ * protocol sends JSON arrays of messages
+ */
+pub trait Transport {
+    fn send(&mut self, messages: Vec<Value>) -> Result<()>;
+
+    // Block until the next batch of messages arrives
+    fn receive(&mut self) -> Result<Vec<Value>>;
+
+    // Wait up to timeout for the next batch of messages, None if nothing arrived in time
+    fn receive_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<Value>>>;
+
+    fn close(&mut self);
+}
+
+pub struct WebSocketTransport {
+    socket: WebSocket<AutoStream>,
+}
+
+impl WebSocketTransport {
+    /* Open a websocket to a ws:// or wss:// url, giving up on a server that doesn't answer
+     * within CONNECT_TIMEOUT rather than waiting on it forever
+     * url: Address of the server
+     */
+    pub fn connect(url: &str) -> Result<WebSocketTransport> {
+        let url = Url::parse(url).map_err(|e| FayeError::Transport(e.to_string()))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| FayeError::Transport(format!("{} has no host", url)))?
+            .to_string();
+        let tcp = connect_tcp(&url)?;
+        // Writes stay bounded afterwards too, so a dead connection can't hang a send
+        tcp.set_read_timeout(Some(CONNECT_TIMEOUT))
+            .and_then(|_| tcp.set_write_timeout(Some(CONNECT_TIMEOUT)))
+            .map_err(|e| FayeError::Transport(e.to_string()))?;
+        let stream = match url.scheme() {
+            "wss" => {
+                let tls = TlsConnector::new().map_err(|e| FayeError::Transport(e.to_string()))?;
+                match tls.connect(&host, tcp) {
+                    Ok(stream) => Stream::Tls(stream),
+                    Err(native_tls::HandshakeError::WouldBlock(_)) => {
+                        return Err(FayeError::TimedOut)
+                    }
+                    Err(native_tls::HandshakeError::Failure(e)) => {
+                        return Err(FayeError::Transport(e.to_string()))
+                    }
+                }
+            }
+            "ws" => Stream::Plain(tcp),
+            scheme => {
+                return Err(FayeError::Transport(format!(
+                    "{} isn't a websocket scheme",
+                    scheme
+                )))
+            }
+        };
+        let (socket, _) = client(url, stream).map_err(|e| match e {
+            HandshakeError::Failure(e) => transport_error(e),
+            HandshakeError::Interrupted(_) => FayeError::TimedOut,
+        })?;
+        let mut transport = WebSocketTransport { socket };
+        transport.set_read_timeout(None)?;
+        Ok(transport)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let tcp = match self.socket.get_ref() {
+            Stream::Plain(tcp) => tcp,
+            Stream::Tls(tls) => tls.get_ref(),
+        };
+        tcp.set_read_timeout(timeout)
+            .map_err(|e| FayeError::Transport(e.to_string()))
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send(&mut self, messages: Vec<Value>) -> Result<()> {
+        let text = Value::Array(messages).to_string();
+        self.socket
+            .write_message(Message::Text(text))
+            .map_err(transport_error)
+    }
+
+    fn receive(&mut self) -> Result<Vec<Value>> {
+        loop {
+            match self.socket.read_message().map_err(transport_error)? {
+                Message::Text(text) => {
+                    return match serde_json::from_str(&text)? {
+                        Value::Array(messages) => Ok(messages),
+                        message => Ok(vec![message]),
+                    };
+                }
+                Message::Close(_) => return Err(FayeError::Closed),
+                // Pings are answered by tungstenite, nothing else is expected
+                _ => {}
+            }
+        }
+    }
+
+    fn receive_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<Value>>> {
+        self.set_read_timeout(Some(timeout))?;
+        let received = self.receive();
+        self.set_read_timeout(None)?;
+        match received {
+            Ok(messages) => Ok(Some(messages)),
+            // A partly read frame is kept by tungstenite and finished on the next read
+            Err(FayeError::TimedOut) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn close(&mut self) {
+        let _ = self.socket.close(None);
+    }
+}
+
+// Connect to the first of the url's addresses that answers within CONNECT_TIMEOUT
+fn connect_tcp(url: &Url) -> Result<TcpStream> {
+    let addrs = url
+        .socket_addrs(|| None)
+        .map_err(|e| FayeError::Transport(e.to_string()))?;
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(tcp) => {
+                let _ = tcp.set_nodelay(true);
+                return Ok(tcp);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) if e.kind() == io::ErrorKind::TimedOut => FayeError::TimedOut,
+        Some(e) => FayeError::Transport(e.to_string()),
+        None => FayeError::Transport(format!("{} has no address", url)),
+    })
+}
+
+fn transport_error(e: tungstenite::Error) -> FayeError {
+    match e {
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+            FayeError::Closed
+        }
+        tungstenite::Error::Io(e)
+            if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            FayeError::TimedOut
+        }
+        e => FayeError::Transport(e.to_string()),
+    }
+}
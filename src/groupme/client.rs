@@ -0,0 +1,435 @@
+use crate::groupme::error::{GroupMeError, Result};
+use crate::groupme::types::*;
+
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use ureq::{Agent, AgentBuilder, Request};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.groupme.com/v3";
+pub const DEFAULT_IMAGE_URL: &str = "https://image.groupme.com";
+
+// Attempts made for a GET before giving up, waiting twice as long after each failure
+const GET_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Clone)]
+pub struct GroupMeClient {
+    token: String,
+    base_url: String,
+    image_url: String,
+    agent: Agent,
+}
+
+impl GroupMeClient {
+    pub fn new(token: &str) -> GroupMeClient {
+        GroupMeClient::with_base_url(token, DEFAULT_BASE_URL)
+    }
+
+    /* Client talking to a host other than api.groupme.com
+     * token: GroupMe access token
+     * base_url: Root of the v3 API, e.g. http://localhost:8080/v3
+     */
+    pub fn with_base_url(token: &str, base_url: &str) -> GroupMeClient {
+        GroupMeClient {
+            token: token.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            image_url: DEFAULT_IMAGE_URL.to_string(),
+            agent: AgentBuilder::new().timeout(Duration::from_secs(30)).build(),
+        }
+    }
+
+    /* Use a different image service for uploads
+     * image_url: Root of the image service, e.g. http://localhost:8081
+     */
+    pub fn with_image_service(mut self, image_url: &str) -> GroupMeClient {
+        self.image_url = image_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn me(&self) -> Result<User> {
+        self.get("/users/me", &[])?
+            .ok_or(GroupMeError::MissingResponse)
+    }
+
+    /* Groups the user belongs to, most recently active first. Membership is omitted to keep
+     * the response small, use group() to get the members of a single group
+     */
+    pub fn groups(&self, page: u32, per_page: u32) -> Result<Vec<Group>> {
+        let query = [
+            ("page", page.to_string()),
+            ("per_page", per_page.to_string()),
+            ("omit", "membership".to_string()),
+        ];
+        Ok(self.get("/groups", &query)?.unwrap_or_default())
+    }
+
+    pub fn group(&self, group_id: &str) -> Result<Group> {
+        self.get(&format!("/groups/{}", group_id), &[])?
+            .ok_or(GroupMeError::MissingResponse)
+    }
+
+    pub fn create_group(&self, group: &GroupUpdate) -> Result<Group> {
+        self.post("/groups", Some(json!(group)))?
+            .ok_or(GroupMeError::MissingResponse)
+    }
+
+    pub fn update_group(&self, group_id: &str, update: &GroupUpdate) -> Result<Group> {
+        self.post(&format!("/groups/{}/update", group_id), Some(json!(update)))?
+            .ok_or(GroupMeError::MissingResponse)
+    }
+
+    /* Delete a group for everyone, only allowed for the group's owner */
+    pub fn destroy_group(&self, group_id: &str) -> Result<()> {
+        let path = format!("/groups/{}/destroy", group_id);
+        self.post::<Value>(&path, None).map(|_| ())
+    }
+
+    pub fn add_members(&self, group_id: &str, members: &[NewMember]) -> Result<MembersAdded> {
+        let body = json!({ "members": members });
+        self.post(&format!("/groups/{}/members/add", group_id), Some(body))?
+            .ok_or(GroupMeError::MissingResponse)
+    }
+
+    /* Remove someone from a group, removing the user's own membership leaves the group
+     * membership_id: Member.id, not the user's id
+     */
+    pub fn remove_member(&self, group_id: &str, membership_id: &str) -> Result<()> {
+        let path = format!("/groups/{}/members/{}/remove", group_id, membership_id);
+        self.post::<Value>(&path, None).map(|_| ())
+    }
+
+    pub fn chats(&self, page: u32, per_page: u32) -> Result<Vec<Chat>> {
+        let query = [
+            ("page", page.to_string()),
+            ("per_page", per_page.to_string()),
+        ];
+        Ok(self.get("/chats", &query)?.unwrap_or_default())
+    }
+
+    /* Page of messages in a conversation, newest first. An empty Vec means there is nothing
+     * (more) to fetch for the given query
+     */
+    pub fn messages(&self, conv: &Conversation, query: &MessageQuery) -> Result<Vec<Message>> {
+        let mut params = Vec::new();
+        if let Some(id) = &query.before_id {
+            params.push(("before_id", id.clone()));
+        }
+        if let Some(id) = &query.since_id {
+            params.push(("since_id", id.clone()));
+        }
+        if let Some(id) = &query.after_id {
+            params.push(("after_id", id.clone()));
+        }
+        if let Some(limit) = query.limit {
+            params.push(("limit", limit.to_string()));
+        }
+
+        match conv {
+            Conversation::Group(id) => {
+                let page: Option<GroupMessagesPage> =
+                    self.get(&format!("/groups/{}/messages", id), &params)?;
+                Ok(page.map(|p| p.messages).unwrap_or_default())
+            }
+            Conversation::Direct(id) => {
+                params.push(("other_user_id", id.clone()));
+                let page: Option<DirectMessagesPage> = self.get("/direct_messages", &params)?;
+                Ok(page.map(|p| p.direct_messages).unwrap_or_default())
+            }
+        }
+    }
+
+    pub fn send_message(&self, conv: &Conversation, message: &NewMessage) -> Result<Message> {
+        match conv {
+            Conversation::Group(id) => {
+                let body = json!({ "message": message });
+                let sent: Option<SentGroupMessage> =
+                    self.post(&format!("/groups/{}/messages", id), Some(body))?;
+                sent.map(|s| s.message).ok_or(GroupMeError::MissingResponse)
+            }
+            Conversation::Direct(id) => {
+                let mut body = json!({ "direct_message": message });
+                body["direct_message"]["recipient_id"] = json!(id);
+                let sent: Option<SentDirectMessage> = self.post("/direct_messages", Some(body))?;
+                sent.map(|s| s.direct_message)
+                    .ok_or(GroupMeError::MissingResponse)
+            }
+        }
+    }
+
+    /* conversation_id: Group id, or the other user's id for direct messages */
+    pub fn like(&self, conversation_id: &str, message_id: &str) -> Result<()> {
+        let path = format!("/messages/{}/{}/like", conversation_id, message_id);
+        self.post::<Value>(&path, None).map(|_| ())
+    }
+
+    pub fn unlike(&self, conversation_id: &str, message_id: &str) -> Result<()> {
+        let path = format!("/messages/{}/{}/unlike", conversation_id, message_id);
+        self.post::<Value>(&path, None).map(|_| ())
+    }
+
+    /* Upload an image to GroupMe's image service, returning the url to use in an image
+     * attachment
+     * image: Raw image data
+     * content_type: Mime type of the image, e.g. image/png
+     */
+    pub fn upload_image(&self, image: &[u8], content_type: &str) -> Result<String> {
+        let resp = self
+            .agent
+            .post(&format!("{}/pictures", self.image_url))
+            .set("X-Access-Token", &self.token)
+            .set("Content-Type", content_type)
+            .send_bytes(image);
+
+        // The image service doesn't wrap its responses like the rest of the API
+        match resp {
+            Ok(resp) => {
+                let upload: ImageUpload = serde_json::from_str(&resp.into_string()?)?;
+                Ok(upload.payload.picture_url)
+            }
+            Err(ureq::Error::Status(status, _)) => Err(GroupMeError::Api {
+                status,
+                errors: Vec::new(),
+            }),
+            // Not using the transport error's Display, which includes the url and so the token
+            Err(ureq::Error::Transport(t)) => Err(transport_error(&t)),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> Request {
+        self.agent
+            .request(method, &format!("{}{}", self.base_url, path))
+            .query("token", &self.token)
+    }
+
+    /* GETs are safe to repeat, so they are retried when the failure looks temporary. POSTs
+     * aren't, as the request may have gone through
+     */
+    fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<Option<T>> {
+        let mut attempt = 1;
+        loop {
+            let mut req = self.request("GET", path);
+            for (key, value) in query {
+                req = req.query(key, value);
+            }
+            match read_response(req.call()) {
+                Err(e) if e.is_transient() && attempt < GET_ATTEMPTS => {
+                    thread::sleep(RETRY_DELAY * 2u32.pow(attempt - 1));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn post<T: DeserializeOwned>(&self, path: &str, body: Option<Value>) -> Result<Option<T>> {
+        let req = self.request("POST", path);
+        read_response(match body {
+            Some(body) => req.send_json(body),
+            None => req.call(),
+        })
+    }
+}
+
+/* Unwrap the response envelope, returning None for empty bodies (304 Not Modified, likes)
+ */
+fn read_response<T: DeserializeOwned>(
+    resp: std::result::Result<ureq::Response, ureq::Error>,
+) -> Result<Option<T>> {
+    match resp {
+        Ok(resp) => {
+            let body = resp.into_string()?;
+            if body.trim().is_empty() {
+                return Ok(None);
+            }
+            let envelope: Envelope<T> = serde_json::from_str(&body)?;
+            Ok(envelope.response)
+        }
+        Err(ureq::Error::Status(status, resp)) => {
+            let errors = resp
+                .into_string()
+                .ok()
+                .and_then(|body| serde_json::from_str::<Envelope<Value>>(&body).ok())
+                .map(|envelope| envelope.meta.errors)
+                .unwrap_or_default();
+            Err(GroupMeError::Api { status, errors })
+        }
+        Err(ureq::Error::Transport(t)) => Err(transport_error(&t)),
+    }
+}
+
+// Built by hand as the transport error's Display includes the url, and with it the token
+fn transport_error(t: &ureq::Transport) -> GroupMeError {
+    let mut msg = t.kind().to_string();
+    if let Some(message) = t.message() {
+        msg.push_str(&format!(": {}", message));
+    }
+    if let Some(source) = t.source() {
+        msg.push_str(&format!(" ({})", source));
+    }
+    GroupMeError::Transport(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    const ME: &str = r#"{"response": {"id": "1", "name": "Ann"}, "meta": {"code": 200}}"#;
+
+    /* Stand-in for the API that answers one request per connection with the given responses in
+     * order, then stops listening. Returns the base url to use and the request lines it received
+     * responses: Status and body of each response
+     */
+    fn serve(responses: Vec<(u16, &'static str)>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v3", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let _ = tx.send(line.trim_end().to_string());
+                // Skip the headers and any body, so the client isn't cut off mid-request
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                let mut stream = reader.into_inner();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Stand-in\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (base_url, rx)
+    }
+
+    #[test]
+    fn response_is_taken_out_of_the_envelope() {
+        let (base_url, requests) = serve(vec![(200, ME)]);
+        let client = GroupMeClient::with_base_url("secret", &base_url);
+        let user = client.me().unwrap();
+        assert_eq!(user.id, "1");
+        assert_eq!(user.name, "Ann");
+        assert_eq!(
+            requests.recv().unwrap(),
+            "GET /v3/users/me?token=secret HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn empty_body_is_no_response() {
+        let (base_url, _requests) = serve(vec![(200, ""), (200, "")]);
+        let client = GroupMeClient::with_base_url("secret", &base_url);
+        assert!(client.like("2", "3").is_ok());
+        assert!(matches!(client.me(), Err(GroupMeError::MissingResponse)));
+    }
+
+    #[test]
+    fn api_errors_come_from_meta() {
+        let body = r#"{"response": null, "meta": {"code": 404, "errors": ["not found"]}}"#;
+        let (base_url, requests) = serve(vec![(404, body)]);
+        let client = GroupMeClient::with_base_url("secret", &base_url);
+        match client.group("2") {
+            Err(GroupMeError::Api { status, errors }) => {
+                assert_eq!(status, 404);
+                assert_eq!(errors, ["not found"]);
+            }
+            result => panic!("expected an Api error, got {:?}", result.map(|g| g.id)),
+        }
+        // Client errors aren't retried
+        assert_eq!(requests.iter().count(), 1);
+    }
+
+    #[test]
+    fn get_is_retried_on_server_errors() {
+        let (base_url, requests) = serve(vec![(503, ""), (502, "Bad Gateway"), (200, ME)]);
+        let client = GroupMeClient::with_base_url("secret", &base_url);
+        assert_eq!(client.me().unwrap().name, "Ann");
+        assert_eq!(requests.iter().count(), 3);
+    }
+
+    #[test]
+    fn get_gives_up_after_its_attempts() {
+        let (base_url, requests) = serve(vec![(500, ""); GET_ATTEMPTS as usize]);
+        let client = GroupMeClient::with_base_url("secret", &base_url);
+        assert_eq!(client.me().unwrap_err().status(), Some(500));
+        assert_eq!(requests.iter().count(), GET_ATTEMPTS as usize);
+    }
+
+    #[test]
+    fn post_is_not_retried() {
+        let (base_url, requests) = serve(vec![(503, "")]);
+        let client = GroupMeClient::with_base_url("secret", &base_url);
+        assert_eq!(client.like("2", "3").unwrap_err().status(), Some(503));
+        assert_eq!(
+            requests.iter().collect::<Vec<_>>(),
+            ["POST /v3/messages/2/3/like?token=secret HTTP/1.1"]
+        );
+    }
+
+    #[test]
+    fn upload_returns_the_picture_url() {
+        let body = r#"{"payload": {"picture_url": "https://i.groupme.com/x"}}"#;
+        let (base_url, requests) = serve(vec![(200, body)]);
+        let image_url = base_url.trim_end_matches("/v3");
+        let client =
+            GroupMeClient::with_base_url("secret", &base_url).with_image_service(image_url);
+        assert_eq!(
+            client
+                .upload_image(&[0x89, b'P', b'N', b'G'], "image/png")
+                .unwrap(),
+            "https://i.groupme.com/x"
+        );
+        assert_eq!(requests.recv().unwrap(), "POST /pictures HTTP/1.1");
+    }
+
+    #[test]
+    fn upload_failure_is_an_api_error() {
+        let (base_url, requests) = serve(vec![(413, "Request Entity Too Large")]);
+        let image_url = base_url.trim_end_matches("/v3");
+        let client =
+            GroupMeClient::with_base_url("secret", &base_url).with_image_service(image_url);
+        match client.upload_image(&[0; 64], "image/jpeg") {
+            Err(GroupMeError::Api { status, errors }) => {
+                assert_eq!(status, 413);
+                assert!(errors.is_empty());
+            }
+            result => panic!("expected an Api error, got {:?}", result),
+        }
+        // Uploads are POSTs, so aren't retried
+        assert_eq!(requests.iter().count(), 1);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{error, fmt, io};
+
+pub type Result<T> = std::result::Result<T, GroupMeError>;
+
+#[derive(Debug)]
+pub enum GroupMeError {
+    // Server answered with a non-success status, errors are taken from the response's meta
+    Api { status: u16, errors: Vec<String> },
+    // Connection level failure (DNS, refused connection, TLS, ...)
+    Transport(String),
+    // Response body wasn't what we expected
+    Decode(serde_json::Error),
+    Io(io::Error),
+    // Successful status, but the envelope had no response in it
+    MissingResponse,
+}
+
+impl GroupMeError {
+    // HTTP status of the failed request, if the server got far enough to send one
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            GroupMeError::Api { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    // Whether trying again later might work, e.g. a dropped connection or GroupMe being down
+    pub fn is_transient(&self) -> bool {
+        match self {
+            GroupMeError::Transport(_) => true,
+            GroupMeError::Api { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for GroupMeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupMeError::Api { status, errors } if errors.is_empty() => {
+                write!(f, "GroupMe returned status {}", status)
+            }
+            GroupMeError::Api { status, errors } => {
+                write!(
+                    f,
+                    "GroupMe returned status {}: {}",
+                    status,
+                    errors.join(", ")
+                )
+            }
+            GroupMeError::Transport(msg) => write!(f, "Couldn't reach GroupMe: {}", msg),
+            GroupMeError::Decode(e) => write!(f, "Unexpected response from GroupMe: {}", e),
+            GroupMeError::Io(e) => write!(f, "Error reading response: {}", e),
+            GroupMeError::MissingResponse => write!(f, "GroupMe sent an empty response"),
+        }
+    }
+}
+
+impl error::Error for GroupMeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            GroupMeError::Decode(e) => Some(e),
+            GroupMeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for GroupMeError {
+    fn from(e: serde_json::Error) -> Self {
+        GroupMeError::Decode(e)
+    }
+}
+
+impl From<io::Error> for GroupMeError {
+    fn from(e: io::Error) -> Self {
+        GroupMeError::Io(e)
+    }
+}
@@ -0,0 +1,9 @@
+// Typed client for the GroupMe v3 REST API. Nothing in here knows about the TUI, so it can be
+// reused by other tools and pointed at a different host (e.g. a local mock server).
+pub mod client;
+pub mod error;
+pub mod types;
+
+pub use client::{GroupMeClient, DEFAULT_BASE_URL, DEFAULT_IMAGE_URL};
+pub use error::{GroupMeError, Result};
+pub use types::*;
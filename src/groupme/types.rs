@@ -0,0 +1,317 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use chrono::prelude::*;
+
+// Every v3 response is wrapped as {"response": ..., "meta": {"code": ..., "errors": [...]}}
+#[derive(Deserialize)]
+pub(crate) struct Envelope<T> {
+    pub response: Option<T>,
+    #[serde(default)]
+    pub meta: Meta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Meta {
+    #[serde(default)]
+    pub code: u16,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+// Group or direct conversation, identified by group id or the other user's id respectively
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Conversation {
+    Group(String),
+    Direct(String),
+}
+
+impl Conversation {
+    pub fn id(&self) -> &str {
+        match self {
+            Conversation::Group(id) | Conversation::Direct(id) => id,
+        }
+    }
+
+    pub fn is_direct(&self) -> bool {
+        matches!(self, Conversation::Direct(_))
+    }
+
+    // Stable string form, usable as a map key or file name for local state
+    pub fn key(&self) -> String {
+        match self {
+            Conversation::Group(id) => format!("group_{}", id),
+            Conversation::Direct(id) => format!("dm_{}", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub email: Option<String>,
+    pub image_url: Option<String>,
+    pub phone_number: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub creator_user_id: Option<String>,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    #[serde(default)]
+    pub members: Vec<Member>,
+    #[serde(default)]
+    pub messages: GroupMessages,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupMessages {
+    #[serde(default)]
+    pub count: u64,
+    pub last_message_id: Option<String>,
+    pub last_message_created_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Member {
+    // Membership id, which is what member removal expects
+    pub id: String,
+    pub user_id: String,
+    pub nickname: String,
+    pub image_url: Option<String>,
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chat {
+    pub other_user: ChatUser,
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    #[serde(default)]
+    pub messages_count: u64,
+    pub last_message: Option<Message>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatUser {
+    pub id: String,
+    pub name: String,
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: String,
+    #[serde(default)]
+    pub source_guid: String,
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub user_id: String,
+    pub group_id: Option<String>,
+    pub recipient_id: Option<String>,
+    #[serde(default)]
+    pub sender_id: String,
+    #[serde(default)]
+    pub name: String,
+    pub avatar_url: Option<String>,
+    pub text: Option<String>,
+    #[serde(default)]
+    pub system: bool,
+    #[serde(default)]
+    pub favorited_by: Vec<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    // Set on system messages such as poll or calendar event announcements
+    pub event: Option<MessageEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEvent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub data: Value,
+}
+
+impl Message {
+    pub fn liked_by(&self, user_id: &str) -> bool {
+        self.favorited_by.iter().any(|id| id == user_id)
+    }
+
+    // Id of the message this one replies to, if it's a reply
+    pub fn reply_id(&self) -> Option<&str> {
+        self.attachments
+            .iter()
+            .find_map(|attachment| match attachment {
+                Attachment::Reply { reply_id, .. } => Some(reply_id.as_str()),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Attachment {
+    Image {
+        #[serde(default)]
+        url: String,
+    },
+    LinkedImage {
+        #[serde(default)]
+        url: String,
+    },
+    Video {
+        #[serde(default)]
+        url: String,
+        preview_url: Option<String>,
+    },
+    File {
+        #[serde(default)]
+        file_id: String,
+        name: Option<String>,
+        size: Option<u64>,
+    },
+    Location {
+        #[serde(default)]
+        name: String,
+        #[serde(default)]
+        lat: String,
+        #[serde(default)]
+        lng: String,
+    },
+    // Placeholder characters in the text stand in for emoji from GroupMe's packs
+    Emoji {
+        #[serde(default)]
+        placeholder: String,
+        #[serde(default)]
+        charmap: Vec<[u64; 2]>,
+    },
+    Mentions {
+        #[serde(default)]
+        user_ids: Vec<String>,
+        #[serde(default)]
+        loci: Vec<[usize; 2]>,
+    },
+    Reply {
+        #[serde(default)]
+        reply_id: String,
+        #[serde(default)]
+        base_reply_id: String,
+    },
+    Poll {
+        #[serde(default)]
+        poll_id: String,
+    },
+    Event {
+        #[serde(default)]
+        event_id: String,
+        view: Option<String>,
+    },
+    Split {
+        #[serde(default)]
+        token: String,
+    },
+    // Anything this client doesn't understand yet
+    #[serde(other)]
+    Unknown,
+}
+
+// Outgoing message, used for both groups and direct messages
+#[derive(Debug, Clone, Serialize)]
+pub struct NewMessage {
+    pub source_guid: String,
+    pub text: String,
+    pub attachments: Vec<Attachment>,
+}
+
+impl NewMessage {
+    pub fn new(text: &str) -> NewMessage {
+        NewMessage {
+            // GroupMe dedupes on source_guid, so it needs to be unique per message
+            source_guid: Utc::now().timestamp_millis().to_string(),
+            text: text.to_string(),
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn attach(mut self, attachment: Attachment) -> NewMessage {
+        self.attachments.push(attachment);
+        self
+    }
+}
+
+// Fields to set when creating or updating a group, None leaves a field as it is
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GroupUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NewMember {
+    pub nickname: String,
+    pub user_id: String,
+}
+
+// Paging parameters for message requests, direct messages only honour before_id and since_id
+#[derive(Debug, Clone, Default)]
+pub struct MessageQuery {
+    pub before_id: Option<String>,
+    pub since_id: Option<String>,
+    pub after_id: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GroupMessagesPage {
+    #[serde(default)]
+    pub messages: Vec<Message>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DirectMessagesPage {
+    #[serde(default)]
+    pub direct_messages: Vec<Message>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SentGroupMessage {
+    pub message: Message,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SentDirectMessage {
+    pub direct_message: Message,
+}
+
+// Members are added asynchronously, results_id can be used to check on the request
+#[derive(Debug, Deserialize)]
+pub struct MembersAdded {
+    pub results_id: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ImageUpload {
+    pub payload: ImagePayload,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ImagePayload {
+    pub picture_url: String,
+}
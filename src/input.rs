@@ -1,9 +1,17 @@
 use crate::app::*;
+use crate::editor;
+use crate::keys::Action;
+use crate::utils::config::LayoutConfig;
 
+use std::io;
 use std::time::Duration;
 
-use crossterm::event::{poll, read, Event, KeyCode};
-use crossterm::Result;
+use crossterm::cursor::Show;
+use crossterm::event::{poll, read, Event, KeyCode, KeyModifiers};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::{execute, Result};
 
 use tui::{backend::Backend, Terminal};
 
@@ -15,158 +23,216 @@ pub fn poll_input<B: Backend>(app: &mut App<'static>, terminal: &mut Terminal<B>
                     app.disp = DispMode::Main;
                 } else {
                     match app.mode {
-                        Modes::GroupNav => {
-                            if event.code == KeyCode::Char('j') || event.code == KeyCode::Down {
-                                app.groups.next();
-                            } else if event.code == KeyCode::Char('k') || event.code == KeyCode::Up
-                            {
-                                app.groups.previous();
-                            } else if event.code == KeyCode::Char('l')
-                                || event.code == KeyCode::Enter
-                                || event.code == KeyCode::Right
-                            {
-                                // Clear input for new group
-                                if app.group_id
-                                    != app.groups.items[app.groups.state.selected().unwrap()].id
-                                {
-                                    app.input.clear();
-                                    app.input.push('▏');
-                                    app.input_pos = 0;
-                                }
-                                app.update_msgs();
-                                app.disp = DispMode::Main;
-                                app.dm = false;
-                                app.mode = Modes::MessageNav;
-                            } else if event.code == KeyCode::Char('q') {
-                                return Ok(false);
-                            } else if event.code == KeyCode::Char('d') {
-                                app.mode = Modes::DirectNav;
-                            } else if event.code == KeyCode::Char('?') {
-                                app.disp = DispMode::Help;
+                        Modes::GroupNav
+                        | Modes::DirectNav
+                        | Modes::MessageNav
+                        | Modes::MemberNav => {
+                            match app.config.keybindings.action(&app.mode, event) {
+                                // Only this needs the terminal, to hand it over to the editor
+                                Some(Action::Editor) => edit_draft(app, terminal)?,
+                                Some(action) if !run_action(app, action) => return Ok(false),
+                                _ => {}
                             }
                         }
-                        Modes::DirectNav => {
-                            if event.code == KeyCode::Char('j') || event.code == KeyCode::Down {
-                                app.dms.next();
-                            } else if event.code == KeyCode::Char('k') || event.code == KeyCode::Up
-                            {
-                                app.dms.previous();
-                            } else if event.code == KeyCode::Char('l')
-                                || event.code == KeyCode::Enter
-                                || event.code == KeyCode::Right
-                            {
-                                // Clear input for new group
-                                if app.dm_id != app.dms.items[app.dms.state.selected().unwrap()].id
-                                {
-                                    app.input.clear();
-                                    app.input.push('▏');
-                                    app.input_pos = 0;
-                                }
-                                app.update_dmsgs();
-                                app.disp = DispMode::Main;
-                                app.dm = true;
+                        Modes::Search => match event.code {
+                            KeyCode::Char(c) => app.search_query.push(c),
+                            KeyCode::Backspace => {
+                                app.search_query.pop();
+                            }
+                            KeyCode::Down if !app.search_results.items.is_empty() => {
+                                app.search_results.next();
+                            }
+                            KeyCode::Up if !app.search_results.items.is_empty() => {
+                                app.search_results.previous();
+                            }
+                            KeyCode::Enter => app.submit_search(),
+                            KeyCode::Esc => {
+                                app.status = None;
                                 app.mode = Modes::MessageNav;
-                            } else if event.code == KeyCode::Char('q') {
-                                return Ok(false);
-                            } else if event.code == KeyCode::Char('g') {
-                                app.mode = Modes::GroupNav;
-                            } else if event.code == KeyCode::Char('?') {
-                                app.disp = DispMode::Help;
                             }
-                        }
-                        Modes::MessageNav => {
-                            if event.code == KeyCode::Char('q') {
-                                return Ok(false);
-                            } else if event.code == KeyCode::Char('r') {
-                                if app.dm {
-                                    app.update_dmsgs();
-                                } else {
-                                    app.update_msgs();
+                            _ => {}
+                        },
+                        Modes::Inputting => {
+                            let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+                            let alt = event.modifiers.contains(KeyModifiers::ALT);
+                            let suggesting = !app.suggestions.items.is_empty();
+                            match event.code {
+                                KeyCode::Char('w') if ctrl => app.composer.kill_word(),
+                                KeyCode::Char('u') if ctrl => app.composer.kill_line(),
+                                KeyCode::Char('z') if ctrl => app.composer.undo(),
+                                KeyCode::Char('o') if ctrl => edit_draft(app, terminal)?,
+                                KeyCode::Char('a') if ctrl => app.composer.home(),
+                                KeyCode::Char('e') if ctrl => app.composer.end(),
+                                KeyCode::Char('b') if alt => app.composer.word_left(),
+                                KeyCode::Char('f') if alt => app.composer.word_right(),
+                                KeyCode::Char(c) if !ctrl && !alt => {
+                                    app.composer.insert(c);
+                                    app.typed();
                                 }
-                            } else if event.code == KeyCode::Char('i') {
-                                app.mode = Modes::Inputting;
-                            } else if event.code == KeyCode::Char('h')
-                                || event.code == KeyCode::Left
-                                || event.code == KeyCode::Esc
-                            {
-                                app.mode = if app.dm {
-                                    Modes::DirectNav
-                                } else {
-                                    Modes::GroupNav
-                                };
-                            } else if event.code == KeyCode::Char('j')
-                                || event.code == KeyCode::Down
-                            {
-                                app.messages.next();
-                            } else if event.code == KeyCode::Char('k') || event.code == KeyCode::Up
-                            {
-                                app.messages.previous();
-                            } else if event.code == KeyCode::Enter {
-                                if app.dm {
-                                    app.dlike();
-                                } else {
-                                    app.like();
+                                KeyCode::Backspace if ctrl || alt => app.composer.kill_word(),
+                                KeyCode::Backspace => app.composer.backspace(),
+                                KeyCode::Delete => app.composer.delete(),
+                                KeyCode::Left if ctrl || alt => app.composer.word_left(),
+                                KeyCode::Right if ctrl || alt => app.composer.word_right(),
+                                KeyCode::Left => app.composer.left(),
+                                KeyCode::Right => app.composer.right(),
+                                KeyCode::Home => app.composer.home(),
+                                KeyCode::End => app.composer.end(),
+                                // Not every terminal tells Shift+Enter apart, Alt+Enter works in most
+                                KeyCode::Enter
+                                    if event
+                                        .modifiers
+                                        .intersects(KeyModifiers::SHIFT | KeyModifiers::ALT) =>
+                                {
+                                    app.composer.newline();
+                                    app.typed();
                                 }
-                            } else if event.code == KeyCode::Char('?') {
-                                app.disp = DispMode::Help;
-                            }
-                        }
-                        Modes::Inputting => match event.code {
-                            KeyCode::Char(c) => {
-                                //app.input.push(c);
-                                app.input.insert(app.input_pos, c);
-                                app.input_pos += 1;
-                            }
-                            KeyCode::Backspace => {
-                                //app.input.pop();
-                                if app.input_pos > 0 {
-                                    app.input.remove(app.input_pos - 1);
-                                    app.input_pos -= 1;
+                                // Input is kept if the command or send failed
+                                KeyCode::Enter => app.submit(),
+                                KeyCode::Tab => {
+                                    app.accept_suggestion();
                                 }
-                            }
-                            KeyCode::Left => {
-                                if app.input_pos > 0 {
-                                    app.input.remove(app.input_pos);
-                                    let tail = app.input.split_off(app.input_pos - 1);
-                                    app.input.push('▏');
-                                    app.input.push_str(&tail);
-                                    app.input_pos -= 1;
+                                KeyCode::Down if suggesting => {
+                                    app.suggestions.next();
                                 }
-                            }
-                            KeyCode::Right => {
-                                if app.input_pos < app.input.chars().count() - 1 {
-                                    app.input.remove(app.input_pos);
-                                    let tail = app.input.split_off(app.input_pos + 1);
-                                    app.input.push('▏');
-                                    app.input.push_str(&tail);
-                                    app.input_pos += 1;
+                                KeyCode::Up if suggesting => {
+                                    app.suggestions.previous();
                                 }
-                            }
-                            KeyCode::Enter => {
-                                app.input.remove(app.input_pos);
-                                if app.dm {
-                                    app.send_dmsg();
-                                } else {
-                                    app.send_msg();
+                                KeyCode::Down => app.composer.down(),
+                                KeyCode::Up => app.composer.up(),
+                                KeyCode::Esc => {
+                                    // Also cancels a reply that hasn't been sent
+                                    app.reply_to = None;
+                                    app.mode = Modes::MessageNav;
                                 }
-                                app.input.push('▏');
-                                app.input_pos = 0;
+                                _ => {}
                             }
-                            KeyCode::Esc => {
-                                app.mode = Modes::MessageNav;
+                            match event.code {
+                                KeyCode::Tab | KeyCode::Up | KeyCode::Down => {}
+                                KeyCode::Esc => app.suggestions.set_items(Vec::new()),
+                                _ => app.update_suggestions(),
                             }
-                            _ => {}
-                        },
+                        }
                     }
                 }
+                // Lists in the sidebar bring it back while they're in use, widening or narrowing
+                // the messages pane
+                app.relayout();
             }
             Event::Mouse(_event) => {
                 return Ok(false);
             }
             Event::Resize(_width, _height) => {
-                app.t_width = (terminal.size().unwrap().width as f64 * 0.98 * 0.70) as u16;
+                let size = terminal.size()?;
+                app.resized(size.width);
             }
         }
     }
     Ok(true)
 }
+
+/* Hand the terminal to $VISUAL or $EDITOR to write the draft there, then load what was saved back
+ * into the message box to be looked over before it's sent
+ * app: App, kinda given
+ * terminal: Terminal to suspend while the editor runs
+ */
+fn edit_draft<B: Backend>(app: &mut App<'static>, terminal: &mut Terminal<B>) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen, Show)?;
+    let edited = editor::edit(app.composer.text());
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    enable_raw_mode()?;
+    // The editor drew over everything, so the next draw has to start from scratch
+    terminal.clear()?;
+    match edited {
+        Ok(text) => {
+            app.composer.set_text(text);
+            app.show_info("Draft loaded from the editor, Enter to send");
+        }
+        Err(e) => app.show_error(e),
+    }
+    app.disp = DispMode::Main;
+    app.mode = Modes::Inputting;
+    Ok(())
+}
+
+/* Carry out an action bound to a key in one of the lists, returning false to quit
+ * app: App, kinda given
+ * action: Action the pressed key is bound to in the current mode
+ */
+fn run_action(app: &mut App<'static>, action: Action) -> bool {
+    match (action, &app.mode) {
+        (Action::Next, Modes::GroupNav) => app.groups.next(),
+        (Action::Next, Modes::DirectNav) => app.dms.next(),
+        (Action::Next, Modes::MessageNav) => app.messages.next(),
+        (Action::Next, Modes::MemberNav) if !app.member_list.items.is_empty() => {
+            app.member_list.next()
+        }
+        (Action::Previous, Modes::GroupNav) => app.groups.previous(),
+        (Action::Previous, Modes::DirectNav) => app.dms.previous(),
+        (Action::Previous, Modes::MessageNav) => app.previous_msg(),
+        (Action::Previous, Modes::MemberNav) if !app.member_list.items.is_empty() => {
+            app.member_list.previous()
+        }
+        (Action::Open, Modes::GroupNav) | (Action::Open, Modes::DirectNav) => {
+            let dm = app.mode == Modes::DirectNav;
+            app.open_conversation(dm);
+            app.disp = DispMode::Main;
+            app.mode = Modes::MessageNav;
+        }
+        (Action::Back, Modes::MessageNav) => {
+            app.mode = if app.dm {
+                Modes::DirectNav
+            } else {
+                Modes::GroupNav
+            };
+        }
+        (Action::Back, Modes::MemberNav) => app.mode = Modes::GroupNav,
+        (Action::Groups, _) => app.mode = Modes::GroupNav,
+        (Action::Dms, _) => app.mode = Modes::DirectNav,
+        (Action::Members, _) => {
+            app.show_members(false);
+            app.disp = DispMode::Main;
+            app.mode = Modes::MemberNav;
+        }
+        (Action::Compose, _) => app.mode = Modes::Inputting,
+        (Action::Reply, _) => {
+            app.reply();
+            app.mode = Modes::Inputting;
+        }
+        (Action::Like, _) => {
+            if app.dm {
+                app.dlike();
+            } else {
+                app.like();
+            }
+        }
+        (Action::Refresh, Modes::MemberNav) => app.show_members(true),
+        (Action::Refresh, _) => {
+            app.refresh_lists();
+            if app.dm {
+                app.update_dmsgs();
+            } else {
+                app.update_msgs();
+            }
+        }
+        (Action::Command, _) => app.start_command(),
+        (Action::Search, _) => {
+            app.status = None;
+            app.disp = DispMode::Main;
+            app.mode = Modes::Search;
+        }
+        (Action::Help, _) => app.disp = DispMode::Help,
+        (Action::Quit, _) => return false,
+        (Action::SidebarWider, _) => app.change_layout(|layout, _| layout.resize_sidebar(5)),
+        (Action::SidebarNarrower, _) => app.change_layout(|layout, _| layout.resize_sidebar(-5)),
+        (Action::GroupsTaller, _) => app.change_layout(|layout, _| layout.resize_groups(5)),
+        (Action::GroupsShorter, _) => app.change_layout(|layout, _| layout.resize_groups(-5)),
+        (Action::InputTaller, _) => app.change_layout(|layout, _| layout.resize_input(2)),
+        (Action::InputShorter, _) => app.change_layout(|layout, _| layout.resize_input(-2)),
+        (Action::ToggleSidebar, _) => app.change_layout(LayoutConfig::toggle_sidebar),
+        (Action::ResetLayout, _) => app.reset_layout(),
+        _ => {}
+    }
+    true
+}
@@ -0,0 +1,486 @@
+use crate::app::Modes;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use serde::Deserialize;
+
+// Something a key can be bound to in [keybindings]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Next,
+    Previous,
+    Open,
+    Back,
+    Groups,
+    Dms,
+    Members,
+    Compose,
+    Editor,
+    Reply,
+    Like,
+    Refresh,
+    Command,
+    Search,
+    Help,
+    Quit,
+    SidebarWider,
+    SidebarNarrower,
+    GroupsTaller,
+    GroupsShorter,
+    InputTaller,
+    InputShorter,
+    ToggleSidebar,
+    ResetLayout,
+}
+
+// Every action, in the order the help screen lists them
+pub const ACTIONS: [Action; 24] = [
+    Action::Next,
+    Action::Previous,
+    Action::Open,
+    Action::Back,
+    Action::Groups,
+    Action::Dms,
+    Action::Members,
+    Action::Compose,
+    Action::Editor,
+    Action::Reply,
+    Action::Like,
+    Action::Refresh,
+    Action::Command,
+    Action::Search,
+    Action::Help,
+    Action::Quit,
+    Action::SidebarWider,
+    Action::SidebarNarrower,
+    Action::GroupsTaller,
+    Action::GroupsShorter,
+    Action::InputTaller,
+    Action::InputShorter,
+    Action::ToggleSidebar,
+    Action::ResetLayout,
+];
+
+// Modes whose keys can be rebound, the others are for typing
+pub const NAV_MODES: [Modes; 4] = [
+    Modes::GroupNav,
+    Modes::DirectNav,
+    Modes::MessageNav,
+    Modes::MemberNav,
+];
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Next => "next",
+            Action::Previous => "previous",
+            Action::Open => "open",
+            Action::Back => "back",
+            Action::Groups => "groups",
+            Action::Dms => "dms",
+            Action::Members => "members",
+            Action::Compose => "compose",
+            Action::Editor => "editor",
+            Action::Reply => "reply",
+            Action::Like => "like",
+            Action::Refresh => "refresh",
+            Action::Command => "command",
+            Action::Search => "search",
+            Action::Help => "help",
+            Action::Quit => "quit",
+            Action::SidebarWider => "sidebar_wider",
+            Action::SidebarNarrower => "sidebar_narrower",
+            Action::GroupsTaller => "groups_taller",
+            Action::GroupsShorter => "groups_shorter",
+            Action::InputTaller => "input_taller",
+            Action::InputShorter => "input_shorter",
+            Action::ToggleSidebar => "toggle_sidebar",
+            Action::ResetLayout => "reset_layout",
+        }
+    }
+
+    // Whether the action changes the layout, which works the same in every mode
+    pub fn is_layout(self) -> bool {
+        matches!(
+            self,
+            Action::SidebarWider
+                | Action::SidebarNarrower
+                | Action::GroupsTaller
+                | Action::GroupsShorter
+                | Action::InputTaller
+                | Action::InputShorter
+                | Action::ToggleSidebar
+                | Action::ResetLayout
+        )
+    }
+
+    /* What the action does in a mode, None if it does nothing there
+     * mode: Mode the key would be pressed in
+     */
+    pub fn describe(self, mode: &Modes) -> Option<&'static str> {
+        let description = match (self, mode) {
+            (Action::Next, Modes::MessageNav) => "Newer message",
+            (Action::Previous, Modes::MessageNav) => "Older message, loading history at the top",
+            (Action::Next, _) => "Move down",
+            (Action::Previous, _) => "Move up",
+            (Action::Open, Modes::GroupNav) => "Open group",
+            (Action::Open, Modes::DirectNav) => "Open direct message",
+            (Action::Back, Modes::MessageNav) | (Action::Back, Modes::MemberNav) => {
+                "Back to the list"
+            }
+            (Action::Groups, Modes::DirectNav) => "Go to groups",
+            (Action::Dms, Modes::GroupNav) => "Go to direct messages",
+            (Action::Members, Modes::GroupNav) => "Show members of the group",
+            (Action::Compose, Modes::MessageNav) => "Write a message",
+            (Action::Editor, Modes::MessageNav) => "Write the message in $VISUAL or $EDITOR",
+            (Action::Reply, Modes::MessageNav) => "Reply to the selected message",
+            (Action::Like, Modes::MessageNav) => "Like or unlike the selected message",
+            (Action::Refresh, Modes::MessageNav) => "Refresh conversations and messages",
+            (Action::Refresh, Modes::MemberNav) => "Refresh members",
+            (Action::Command, Modes::MemberNav) | (Action::Search, Modes::MemberNav) => {
+                return None
+            }
+            (Action::Command, _) => "Run a command",
+            (Action::Search, _) => "Search messages",
+            (Action::Help, _) => "Show this help",
+            (Action::Quit, _) => "Quit",
+            (Action::SidebarWider, _) => "Widen the sidebar",
+            (Action::SidebarNarrower, _) => "Narrow the sidebar",
+            (Action::GroupsTaller, _) => "Give groups more of the sidebar",
+            (Action::GroupsShorter, _) => "Give direct messages more of the sidebar",
+            (Action::InputTaller, _) => "Make the message box taller",
+            (Action::InputShorter, _) => "Make the message box shorter",
+            (Action::ToggleSidebar, _) => "Hide or show the sidebar",
+            (Action::ResetLayout, _) => "Go back to the layout in config.toml",
+            _ => return None,
+        };
+        Some(description)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Key, with modifiers, as written in [keybindings] e.g. "j", "down" or "ctrl+r"
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub struct Key(KeyEvent);
+
+impl Key {
+    /* Shift is part of the character for letters and symbols, and of BackTab, so it's only kept
+     * for other keys
+     */
+    pub fn from_event(event: KeyEvent) -> Key {
+        let mut modifiers = event.modifiers;
+        if let KeyCode::Char(_) | KeyCode::BackTab = event.code {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Key(KeyEvent::new(event.code, modifiers))
+    }
+}
+
+impl TryFrom<String> for Key {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::empty();
+        let mut rest = name.as_str();
+        // A lone + is the plus key, otherwise it separates modifiers from the key
+        while let Some((modifier, key)) = rest.split_once('+').filter(|(_, key)| !key.is_empty()) {
+            match modifier.to_lowercase().as_ref() {
+                "ctrl" | "control" => modifiers.insert(KeyModifiers::CONTROL),
+                "alt" => modifiers.insert(KeyModifiers::ALT),
+                "shift" => modifiers.insert(KeyModifiers::SHIFT),
+                _ => return Err(format!("unknown modifier {} in {}", modifier, name)),
+            }
+            rest = key;
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_ref() {
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "space" => KeyCode::Char(' '),
+                function if function.starts_with('f') => match function[1..].parse() {
+                    Ok(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key {}", name)),
+                },
+                _ => return Err(format!("unknown key {}", name)),
+            },
+        };
+        // Terminals send Shift+r as R and Shift+Tab as BackTab
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_uppercase().next().unwrap_or(c))
+            }
+            KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            code => code,
+        };
+        Ok(Key::from_event(KeyEvent::new(code, modifiers)))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let KeyEvent { code, modifiers } = self.0;
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+        match code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::Esc => write!(f, "Esc"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+// [keybindings] table, the keys bound to each action
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "HashMap<String, Vec<Key>>")]
+pub struct KeyMap(HashMap<Action, Vec<Key>>);
+
+// Table keys are plain strings in TOML, so actions are looked up by name
+impl TryFrom<HashMap<String, Vec<Key>>> for KeyMap {
+    type Error = String;
+
+    fn try_from(table: HashMap<String, Vec<Key>>) -> Result<Self, String> {
+        let mut map = HashMap::new();
+        for (name, keys) in table {
+            let action = ACTIONS
+                .iter()
+                .copied()
+                .find(|action| action.name() == name)
+                .ok_or_else(|| format!("unknown action {}", name))?;
+            map.insert(action, keys);
+        }
+        Ok(KeyMap(map))
+    }
+}
+
+impl KeyMap {
+    /* Action bound to a key in a mode, if any
+     * mode: Mode the key was pressed in
+     * event: Key that was pressed
+     */
+    pub fn action(&self, mode: &Modes, event: KeyEvent) -> Option<Action> {
+        let key = Key::from_event(event);
+        ACTIONS
+            .iter()
+            .copied()
+            .find(|action| action.describe(mode).is_some() && self.keys(*action).contains(&key))
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.0
+            .get(&action)
+            .map(|keys| keys.as_slice())
+            .unwrap_or_default()
+    }
+
+    /* First key bound to two actions that both do something in the same mode, returned as the
+     * later action, the key, the earlier action and the mode
+     */
+    pub fn conflict(&self) -> Option<(Action, Key, Action, &'static str)> {
+        for mode in NAV_MODES.iter() {
+            let mut seen: HashMap<Key, Action> = HashMap::new();
+            for action in ACTIONS.iter().copied() {
+                if action.describe(mode).is_none() {
+                    continue;
+                }
+                for key in self.keys(action) {
+                    match seen.get(key) {
+                        Some(&other) if other != action => {
+                            return Some((action, *key, other, mode_name(mode)))
+                        }
+                        _ => {
+                            seen.insert(*key, action);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+// Name of a navigation mode as shown on the help screen
+pub fn mode_name(mode: &Modes) -> &'static str {
+    match mode {
+        Modes::GroupNav => "Groups",
+        Modes::DirectNav => "Direct Messages",
+        Modes::MessageNav => "Messages",
+        Modes::MemberNav => "Members",
+        Modes::Inputting => "Composing",
+        Modes::Search => "Search",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Key {
+        Key::try_from(name.to_string()).unwrap()
+    }
+
+    fn pressed(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key::from_event(KeyEvent::new(code, modifiers))
+    }
+
+    fn keymap(bindings: &[(&str, &[&str])]) -> KeyMap {
+        let table = bindings
+            .iter()
+            .map(|(action, keys)| {
+                let keys = keys.iter().map(|name| key(name)).collect();
+                (action.to_string(), keys)
+            })
+            .collect::<HashMap<_, _>>();
+        KeyMap::try_from(table).unwrap()
+    }
+
+    #[test]
+    fn parses_modifiers() {
+        assert_eq!(
+            key("ctrl+r"),
+            pressed(KeyCode::Char('r'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            key("Control+Alt+x"),
+            pressed(
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            )
+        );
+        assert_eq!(key("shift+up"), pressed(KeyCode::Up, KeyModifiers::SHIFT));
+    }
+
+    #[test]
+    fn shifted_letters_are_capitals() {
+        let shift_r = pressed(KeyCode::Char('R'), KeyModifiers::SHIFT);
+        assert_eq!(key("R"), shift_r);
+        assert_eq!(key("shift+r"), shift_r);
+        assert_ne!(key("r"), shift_r);
+    }
+
+    #[test]
+    fn shift_tab_is_backtab() {
+        let shift_tab = pressed(KeyCode::BackTab, KeyModifiers::SHIFT);
+        assert_eq!(key("shift+tab"), shift_tab);
+        assert_eq!(key("backtab"), shift_tab);
+        assert_ne!(key("tab"), shift_tab);
+    }
+
+    #[test]
+    fn parses_named_keys() {
+        assert_eq!(key("f1"), pressed(KeyCode::F(1), KeyModifiers::NONE));
+        assert_eq!(key("F12"), pressed(KeyCode::F(12), KeyModifiers::NONE));
+        assert_eq!(key("Enter"), pressed(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(
+            key("space"),
+            pressed(KeyCode::Char(' '), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            key("pagedown"),
+            pressed(KeyCode::PageDown, KeyModifiers::NONE)
+        );
+    }
+
+    #[test]
+    fn plus_is_a_key_on_its_own() {
+        assert_eq!(key("+"), pressed(KeyCode::Char('+'), KeyModifiers::NONE));
+        assert_eq!(
+            key("ctrl++"),
+            pressed(KeyCode::Char('+'), KeyModifiers::CONTROL)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(Key::try_from("f13".to_string()).is_err());
+        assert!(Key::try_from("f".to_string()).is_ok());
+        assert!(Key::try_from("enterr".to_string()).is_err());
+        assert_eq!(
+            Key::try_from("hyper+x".to_string()).unwrap_err(),
+            "unknown modifier hyper in hyper+x"
+        );
+        assert!(KeyMap::try_from(
+            vec![("jump".to_string(), vec![key("j")])]
+                .into_iter()
+                .collect::<HashMap<_, _>>()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn conflict_names_the_actions_key_and_mode() {
+        let keymap = keymap(&[("next", &["j"]), ("like", &["down", "j"])]);
+        assert_eq!(
+            keymap.conflict(),
+            Some((Action::Like, key("j"), Action::Next, "Messages"))
+        );
+    }
+
+    #[test]
+    fn same_key_in_different_modes_is_no_conflict() {
+        // Open does nothing in the message list, and like does nothing anywhere else
+        let keymap = keymap(&[("open", &["enter"]), ("like", &["enter"])]);
+        assert_eq!(keymap.conflict(), None);
+        assert_eq!(
+            keymap.action(&Modes::GroupNav, KeyEvent::from(KeyCode::Enter)),
+            Some(Action::Open)
+        );
+        assert_eq!(
+            keymap.action(&Modes::MessageNav, KeyEvent::from(KeyCode::Enter)),
+            Some(Action::Like)
+        );
+    }
+
+    #[test]
+    fn default_keys_have_no_conflicts() {
+        let config: toml::Value =
+            toml::from_str(include_str!("utils/default_config.toml")).unwrap();
+        let keymap: KeyMap = config["keybindings"].clone().try_into().unwrap();
+        assert_eq!(keymap.conflict(), None);
+        let reply = KeyEvent::new(KeyCode::Char('R'), KeyModifiers::SHIFT);
+        assert_eq!(
+            keymap.action(&Modes::MessageNav, reply),
+            Some(Action::Reply)
+        );
+        assert_eq!(
+            keymap.action(&Modes::MessageNav, KeyEvent::from(KeyCode::Char('r'))),
+            Some(Action::Refresh)
+        );
+    }
+}
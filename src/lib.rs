@@ -1,7 +1,21 @@
 pub mod api;
 pub mod app;
+pub mod cache;
+pub mod cli;
+pub mod commands;
+pub mod composer;
 pub mod draw;
+pub mod editor;
+pub mod error;
+pub mod export;
+pub mod faye;
+pub mod groupme;
 pub mod input;
+pub mod keys;
 pub mod listener;
 pub mod lists;
+pub mod mentions;
+pub mod notifications;
+pub mod search;
+pub mod theme;
 pub mod utils;
@@ -1,175 +1,373 @@
-use std::error::Error;
+use crate::faye::{Advice, FayeClient, FayeError, Published, Transport, WebSocketTransport};
+use crate::groupme::{self, Conversation};
+use crate::notifications::Notifier;
+
 use std::sync::mpsc;
+use std::thread;
+use std::time;
 
 use serde_json::{json, Value};
 
-use tungstenite::{client::AutoStream, connect, protocol::WebSocket, Message};
-
-use url::Url;
-
 use chrono::prelude::*;
 use chrono::Duration;
 
-use notify_rust::Notification;
+const PUSH_URL: &str = "wss://push.groupme.com/faye";
 
-#[cfg(target_os = "macos")]
-static SOUND: &'static str = "Ping";
+// Longest wait between attempts to reconnect, in seconds
+const MAX_BACKOFF: u64 = 60;
 
-#[cfg(all(unix, not(target_os = "macos")))]
-static SOUND: &str = "message-new-instant";
+// How long to wait on the push server before checking for commands from the main thread
+const POLL_TIMEOUT: time::Duration = time::Duration::from_millis(200);
 
-#[cfg(target_os = "windows")]
-static SOUND: &'static str = "Mail";
+// Requests from the main thread
+pub enum ListenerCommand {
+    Shutdown,
+    // Conversation being viewed, whose typing events are wanted
+    Focus(Option<Conversation>),
+    // User is typing in the focused conversation
+    Typing,
+}
 
+// Events forwarded to the main loop so the UI can update without refreshing
+pub enum PushEvent {
+    // New message in a group or direct conversation
+    Message(Conversation, groupme::Message),
+    // Existing message changed, e.g. it was liked or edited
+    Update(Conversation, groupme::Message),
+    // Push connection went up or down
+    Connection(ConnectionState),
+    // Someone, given by user id, is typing in a conversation
+    Typing(Conversation, String),
+}
+
+// State of the connection to the push server, shown in the status bar
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    // Waiting delay seconds before trying again
+    Reconnecting { attempt: u32, delay: u64 },
+    // Not trying again, e.g. the server advised against reconnecting
+    Offline(String),
+}
+
+// Why a session with the push server ended without an error
+enum Ended {
+    Shutdown,
+    Handshake,
+    Stop,
+}
 
-/* Call on seperate thread to poll for new notifications, and then send a desktop notification
- * with the contents
+/* Call on seperate thread to poll for new notifications, and then notify the user of the ones
+ * the notification rules allow. Reconnects with exponential backoff whenever the connection
+ * drops, unless the server advises against it
  *
- * rx: mspc::Reciever<bool> - channel to send shutdown signal from the main thread
+ * rx: mspc::Reciever<ListenerCommand> - channel to send shutdown, focus and typing from the
+ * main thread
+ * events: mpsc::Sender<PushEvent> - channel to forward new messages to the main thread
  * user_id: &str - User's ID
  * secret: API Key
+ * notifier: Notification rules from config.toml
  */
-pub fn listener(rx: mpsc::Receiver<bool>, user_id: &str, secret: &str) {
-    let mut id: u64 = 1;
+pub fn listener(
+    rx: mpsc::Receiver<ListenerCommand>,
+    events: mpsc::Sender<PushEvent>,
+    user_id: &str,
+    secret: &str,
+    notifier: Notifier,
+) {
+    let mut attempt = 0;
+    let mut focus = None;
+    loop {
+        let _ = events.send(PushEvent::Connection(ConnectionState::Connecting));
+        match session(
+            &rx,
+            &events,
+            user_id,
+            secret,
+            &notifier,
+            &mut attempt,
+            &mut focus,
+        ) {
+            Ok(Ended::Shutdown) => return,
+            Ok(Ended::Handshake) => continue,
+            Ok(Ended::Stop) => {
+                let _ = events.send(PushEvent::Connection(ConnectionState::Offline(
+                    "GroupMe asked not to reconnect".to_string(),
+                )));
+                return;
+            }
+            Err(_) => {}
+        }
 
+        attempt += 1;
+        let delay = backoff(attempt);
+        let _ = events.send(PushEvent::Connection(ConnectionState::Reconnecting {
+            attempt,
+            delay,
+        }));
+        // Waiting on the command channel rather than sleeping, so quitting isn't held up
+        let until = time::Instant::now() + time::Duration::from_secs(delay);
+        loop {
+            let left = until.saturating_duration_since(time::Instant::now());
+            match rx.recv_timeout(left) {
+                Ok(ListenerCommand::Shutdown) | Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                Ok(ListenerCommand::Focus(conv)) => focus = conv,
+                Ok(ListenerCommand::Typing) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+            }
+        }
+    }
+}
 
-    let (mut socket, _) = connect(Url::parse("wss://push.groupme.com/faye").unwrap())
-        .expect("Couldn't connect socket");
+// Seconds to wait before the given reconnect attempt: 1, 2, 4, ... up to MAX_BACKOFF
+fn backoff(attempt: u32) -> u64 {
+    2u64.saturating_pow(attempt.saturating_sub(1)).min(MAX_BACKOFF)
+}
 
-    let mut client_id = subscribe(&mut socket, secret, user_id, &mut id);
+/* Connect, subscribe to the user's channel and poll until the connection fails or the server
+ * advises otherwise. Commands from the main thread are handled between polls
+ *
+ * rx: Commands from the main thread
+ * events: Channel to forward events to the main thread
+ * user_id: User's id
+ * secret: API key
+ * notifier: Notification rules
+ * attempt: Failed attempts so far, reset once subscribed
+ * focus: Conversation being viewed, kept across sessions
+ */
+fn session(
+    rx: &mpsc::Receiver<ListenerCommand>,
+    events: &mpsc::Sender<PushEvent>,
+    user_id: &str,
+    secret: &str,
+    notifier: &Notifier,
+    attempt: &mut u32,
+    focus: &mut Option<Conversation>,
+) -> Result<Ended, FayeError> {
+    let mut client = FayeClient::new(WebSocketTransport::connect(PUSH_URL)?);
+    subscribe(&mut client, secret, user_id, focus.as_ref())?;
     let mut last_hs = Local::now();
+    *attempt = 0;
+    let _ = events.send(PushEvent::Connection(ConnectionState::Connected));
+    client.start_connect()?;
 
     loop {
-        // Check for shutdown signal
-        match rx.try_recv() {
-            Ok(shutdown) => {
-                if shutdown {
-                    socket.close(None).unwrap();
-                    return;
+        loop {
+            match rx.try_recv() {
+                // Unable to receive shutdown signal once disconnected, returning to be safe
+                Ok(ListenerCommand::Shutdown) | Err(mpsc::TryRecvError::Disconnected) => {
+                    client.disconnect();
+                    return Ok(Ended::Shutdown);
                 }
+                Ok(ListenerCommand::Focus(conv)) => {
+                    if let Some(old) = focus.as_ref() {
+                        watch(client.unsubscribe(&channel(old, user_id), Some(ext(secret))))?;
+                    }
+                    if let Some(new) = conv.as_ref() {
+                        watch(client.subscribe(&channel(new, user_id), Some(ext(secret))))?;
+                    }
+                    *focus = conv;
+                }
+                Ok(ListenerCommand::Typing) => {
+                    if let Some(conv) = focus.as_ref() {
+                        let typing = json!({
+                            "type": "typing",
+                            "user_id": user_id,
+                            "started": Local::now().timestamp_millis(),
+                        });
+                        watch(client.publish(&channel(conv, user_id), typing, Some(ext(secret))))?;
+                    }
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
             }
-            Err(mpsc::TryRecvError::Disconnected) => {
-                // Unable to receive shutdown signal, returning to be safe
-                return;
-            }
-            Err(mpsc::TryRecvError::Empty) => {}
         }
 
-        if last_hs.timestamp() + Duration::hours(1).num_seconds() < Local::now().timestamp() {
-            client_id = subscribe(&mut socket, secret, user_id, &mut id);
-            last_hs = Local::now();
+        let advice = client.poll(POLL_TIMEOUT)?;
+        for published in client.take_published() {
+            // Main thread has gone away, nothing left to update
+            if !dispatch(&published, user_id, events, notifier, focus.as_ref()) {
+                return Ok(Ended::Shutdown);
+            }
         }
 
-        poll(&mut socket, &client_id, &mut id).unwrap();
+        match advice {
+            None => {}
+            Some(Advice::Retry(interval)) => {
+                if interval > 0 {
+                    thread::sleep(time::Duration::from_millis(interval));
+                }
+                if last_hs.timestamp() + Duration::hours(1).num_seconds() < Local::now().timestamp()
+                {
+                    subscribe(&mut client, secret, user_id, focus.as_ref())?;
+                    last_hs = Local::now();
+                }
+                client.start_connect()?;
+            }
+            Some(Advice::Handshake) => return Ok(Ended::Handshake),
+            Some(Advice::None) => return Ok(Ended::Stop),
+        }
     }
-
 }
 
-/* Subscribe to push notifications using websockets
+/* Handshake and subscribe to the user's channel, which receives every message and like, and to
+ * the focused conversation's channel for typing events
  *
- * socket: Websocket connected to wss://push.groupme.com/faye
+ * client: Faye client connected to the push server
  * secret: API key
  * user_id: User's id
- * id: Incrementing id for communicating with GroupMe's servers
+ * focus: Conversation being viewed
  */
-fn subscribe(
-    socket: &mut WebSocket<AutoStream>,
+fn subscribe<T: Transport>(
+    client: &mut FayeClient<T>,
     secret: &str,
     user_id: &str,
-    id: &mut u64,
-) -> String {
-    // First do handshake
-    let hs = json!(
-    [{
-        "channel": "/meta/handshake",
-        "version": "1.0",
-        "supportedConnectionTypes": ["websocket"],
-        "id": id.to_string(),
-    }]
-    );
-
-    socket
-        .write_message(Message::Text(hs.to_string()))
-        .expect("Error handshaking");
-    *id += 1;
-
-    let resp = socket.read_message().expect("Error receiving handshake");
-    let resp_json: Value =
-        serde_json::from_str(resp.to_text().unwrap()).expect("Error parsing json");
-
-
-    let client_id = resp_json.as_array().unwrap()[0]["clientId"]
-        .as_str()
-        .unwrap();
-    let timestamp = Local::now().timestamp().to_string();
-
-    // Subscribe to user channel to recieve notifications when polling
-    let sub_msg = json!(
-    [{
-        "channel": "/meta/subscribe",
-        "clientId": client_id,
-        "subscription": format!("/user/{}", user_id),
-        "id": id.to_string(),
-        "ext": {
-            "access_token": secret,
-            "timestamp": timestamp
+    focus: Option<&Conversation>,
+) -> Result<(), FayeError> {
+    client.handshake()?;
+    client.subscribe(&format!("/user/{}", user_id), Some(ext(secret)))?;
+    if let Some(conv) = focus {
+        watch(client.subscribe(&channel(conv, user_id), Some(ext(secret))))?;
+    }
+    Ok(())
+}
+
+// Credentials GroupMe expects with subscriptions and publishes
+fn ext(secret: &str) -> Value {
+    json!({
+        "access_token": secret,
+        "timestamp": Local::now().timestamp().to_string(),
+    })
+}
+
+// Typing events are a nicety, the server turning one down isn't worth reconnecting over
+fn watch(result: Result<(), FayeError>) -> Result<(), FayeError> {
+    match result {
+        Err(FayeError::Refused { .. }) => Ok(()),
+        result => result,
+    }
+}
+
+/* Channel a conversation's typing events are published on. Direct message channels are named
+ * after both participants, lowest id first
+ * conv: Group or direct message
+ * user_id: User's id
+ */
+fn channel(conv: &Conversation, user_id: &str) -> String {
+    match conv {
+        Conversation::Group(id) => format!("/group/{}", id),
+        Conversation::Direct(other) => {
+            let mut ids = [user_id, other.as_str()];
+            ids.sort_by_key(|id| (id.len(), *id));
+            format!("/direct_message/{}+{}", ids[0], ids[1])
         }
-    }]
-    );
+    }
+}
 
-    // Write subscription
-    socket
-        .write_message(Message::Text(sub_msg.to_string()))
-        .expect("Error Subscribing");
-    *id += 1;
+/* Forward a pushed message to the main thread and notify the user of it if the rules allow,
+ * returning false once the main thread has gone away
+ *
+ * published: Message pushed on one of our channels
+ * user_id: User's id, used to work out which conversation a direct message belongs to
+ * events: Channel to forward parsed events to the main thread
+ * notifier: Notification rules
+ * focus: Conversation being viewed
+ */
+fn dispatch(
+    published: &Published,
+    user_id: &str,
+    events: &mpsc::Sender<PushEvent>,
+    notifier: &Notifier,
+    focus: Option<&Conversation>,
+) -> bool {
+    // Conversation channels repeat what the user channel sends, only their typing events are new
+    if !published.channel.starts_with("/user/") {
+        return match parse_typing(published, user_id) {
+            Some(event) => events.send(event).is_ok(),
+            None => true,
+        };
+    }
+
+    let data = &published.data;
+    let event = parse_event(data, user_id);
+    if let Some(alert) = data["alert"].as_str() {
+        let allowed = match &event {
+            Some(PushEvent::Message(conv, msg)) => notifier.allows(Some(conv), Some(msg), focus),
+            Some(PushEvent::Update(conv, _)) => notifier.allows(Some(conv), None, focus),
+            _ => notifier.allows(None, None, focus),
+        };
+        if allowed {
+            notifier.notify(alert);
+        }
+    }
 
-    // Read confirmation message to empty queue
-    socket
-        .read_message()
-        .expect("Error recieving sub confirmation");
+    match event {
+        Some(event) => events.send(event).is_ok(),
+        None => true,
+    }
+}
 
-    client_id.to_string()
+/* Turn a typing event on a conversation channel into a PushEvent, ignoring the user's own
+ * published: Message pushed on a group or direct message channel
+ * user_id: User's id
+ */
+fn parse_typing(published: &Published, user_id: &str) -> Option<PushEvent> {
+    let data = &published.data;
+    if data["type"].as_str()? != "typing" {
+        return None;
+    }
+    let typist = match &data["user_id"] {
+        Value::String(id) => id.clone(),
+        Value::Number(id) => id.to_string(),
+        _ => return None,
+    };
+    if typist == user_id {
+        return None;
+    }
+    let conv = if let Some(id) = published.channel.strip_prefix("/group/") {
+        Conversation::Group(id.to_string())
+    } else {
+        let ids = published.channel.strip_prefix("/direct_message/")?;
+        let other = ids.split('+').find(|id| *id != user_id)?;
+        Conversation::Direct(other.to_string())
+    };
+    Some(PushEvent::Typing(conv, typist))
 }
 
-/* Poll push notification server once
- * socket: &mut WebSocket<AutoStream> - Socket connected to wss:://push.groupme.com/faye
- * client_id: &str - Current polling signature, obtained from handshake
- * id: Incrementing id for communicating with GroupMe's servers
+/* Turn the data of a pushed user channel message into a PushEvent, ignoring types we don't handle
+ * data: "data" object of the pushed message
+ * user_id: User's id
  */
-fn poll(socket: &mut WebSocket<AutoStream>, client_id: &str, id: &mut u64) -> Result<(), Box<dyn Error>> {
-    let poll_msg = json!(
-        [{
-            "channel": "/meta/connect",
-            "clientId": client_id,
-            "connectionType": "websocket",
-            "id": id
-        }]
-        );
-    socket.write_message(Message::Text(poll_msg.to_string())).expect("Error polling faye");
-    *id += 1;
-
-    let resp = socket.read_message().expect("Error reading message");
-
-    // As far as I know, the only text responses that will be sent are notifications
-    if resp.is_text() {
-        let msg = resp.to_text().unwrap();
-        let poll_json: Value = serde_json::from_str(&msg)?;
-        if poll_json.as_array().unwrap().len() == 1 {
-            return Ok(())
+pub fn parse_event(data: &Value, user_id: &str) -> Option<PushEvent> {
+    let subject = &data["subject"];
+    match data["type"].as_str()? {
+        "line.create" | "direct_message.create" => {
+            let msg: groupme::Message = serde_json::from_value(subject.clone()).ok()?;
+            Some(PushEvent::Message(conversation_of(&msg, user_id), msg))
         }
-        let poll_results = poll_json.as_array().unwrap()[1].as_object().unwrap();
+        "like.create" | "like.delete" | "favorite" | "line.update" | "direct_message.update" => {
+            // Likes wrap the updated message, edits send it directly
+            let inner = if !subject["line"].is_null() {
+                &subject["line"]
+            } else if !subject["direct_message"].is_null() {
+                &subject["direct_message"]
+            } else {
+                subject
+            };
+            let msg: groupme::Message = serde_json::from_value(inner.clone()).ok()?;
+            Some(PushEvent::Update(conversation_of(&msg, user_id), msg))
+        }
+        _ => None,
+    }
+}
 
-        if !poll_results.contains_key("data") || !poll_results["data"].as_object().unwrap().contains_key("alert") {
-            return Ok(())
+// Group the message was sent to, or the other participant of a direct message
+fn conversation_of(msg: &groupme::Message, user_id: &str) -> Conversation {
+    match &msg.group_id {
+        Some(group_id) => Conversation::Group(group_id.clone()),
+        None if msg.sender_id == user_id => {
+            Conversation::Direct(msg.recipient_id.clone().unwrap_or_default())
         }
-        
-        let alert = poll_results["data"]["alert"].as_str().unwrap();
-        Notification::new()
-            .summary("GroupMe")
-            .sound_name(SOUND)
-            .icon("mail-unread")
-            .body(&alert)
-            .show()?;
+        None => Conversation::Direct(msg.sender_id.clone()),
     }
-    Ok(())
 }
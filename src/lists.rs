@@ -1,25 +1,50 @@
 // Let's say we have some events to display.
+use crate::groupme::{Attachment, Message};
+
+use std::cmp::Ordering;
+
+use serde::Serialize;
+
 use tui::text::Text;
 use tui::widgets::ListState;
 
+#[derive(Serialize)]
 pub struct GroupInfo {
     pub name: String,
     pub id: String,
     pub lmid: String,
     pub message_count: u64,
+    // Messages that arrived since the group was last read
+    pub unread: u64,
+    // Timestamp of the newest message, used to order the list
+    pub last_activity: i64,
 }
 
+#[derive(Serialize)]
 pub struct DirectMessage {
     pub name: String,
     pub id: String,
+    pub lmid: String,
+    pub message_count: u64,
+    pub unread: u64,
+    pub last_activity: i64,
 }
 
 pub struct MsgInfo<'a> {
+    // Message as received, kept to render it again when it changes
+    pub message: Message,
     pub id: String,
+    pub name: String,
+    pub text: String,
     pub num_likes: usize,
     pub display: Text<'a>,
-    pub attachments: Vec<String>,
+    pub attachments: Vec<Attachment>,
     pub liked: bool,
+    // Message this one replies to, and the first message of that reply chain
+    pub reply_to: Option<String>,
+    pub base_reply_id: Option<String>,
+    // Excerpt of the message replied to, None if it isn't a reply or that message isn't known
+    pub quote: Option<String>,
 }
 
 pub struct Lists<T> {
@@ -37,7 +62,7 @@ impl<T> Lists<T> {
             items,
             state: ListState::default(),
         };
-        if group {
+        if group && !list.items.is_empty() {
             list.state.select(Some(0));
         }
         list
@@ -50,9 +75,50 @@ impl<T> Lists<T> {
         self.state = ListState::default();
     }
 
+    // Add items in front of the current ones, e.g. older messages. The selection is shifted so
+    // that it stays on the same item
+    pub fn prepend_items(&mut self, mut items: Vec<T>) {
+        let added = items.len();
+        items.append(&mut self.items);
+        self.items = items;
+        if let Some(i) = self.state.selected() {
+            self.state.select(Some(i + added));
+        }
+    }
+
+    // Remove an item, keeping a valid selection
+    pub fn remove(&mut self, index: usize) -> T {
+        let item = self.items.remove(index);
+        let selected = match self.state.selected() {
+            _ if self.items.is_empty() => None,
+            Some(i) if i > index || i == self.items.len() => Some(i - 1),
+            selected => selected,
+        };
+        self.state.select(selected);
+        item
+    }
+
+    // Sort the items, keeping the selection on the same item
+    pub fn sort_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let selected = self.state.selected();
+        let mut indexed: Vec<(usize, T)> = self.items.drain(..).enumerate().collect();
+        indexed.sort_by(|a, b| compare(&a.1, &b.1));
+        if let Some(i) = selected {
+            let new_pos = indexed.iter().position(|(old, _)| *old == i);
+            self.state.select(new_pos);
+        }
+        self.items = indexed.into_iter().map(|(_, item)| item).collect();
+    }
+
     // Select the next item. This will not be reflected until the widget is drawn in the
     // `Terminal::draw` callback using `Frame::render_stateful_widget`.
     pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.items.len() - 1 {
@@ -69,6 +135,9 @@ impl<T> Lists<T> {
     // Select the previous item. This will not be reflected until the widget is drawn in the
     // `Terminal::draw` callback using `Frame::render_stateful_widget`.
     pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -1,15 +1,10 @@
-mod api;
-mod app;
-mod draw;
-mod input;
-mod listener;
-mod lists;
-mod utils;
-
 //use std::{borrow::Borrow, io};
+use std::env;
 use std::error::Error;
 use std::io;
-use std::sync::mpsc;
+use std::panic;
+use std::process;
+use std::sync::mpsc::{self, TryRecvError};
 use std::thread;
 
 use crossterm::{
@@ -19,41 +14,119 @@ use crossterm::{
 
 use tui::{backend::CrosstermBackend, Terminal};
 
-use app::*;
-use draw::*;
-use input::*;
-use listener::listener;
+use groupme_tui::app::*;
+use groupme_tui::cli::{self, Command};
+use groupme_tui::draw::*;
+use groupme_tui::input::*;
+use groupme_tui::listener::{listener, ConnectionState, ListenerCommand};
+use groupme_tui::notifications::Notifier;
+use groupme_tui::utils;
+use groupme_tui::utils::config::Config;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let config = utils::config::get_configs().unwrap();
-    let (tx, rx) = mpsc::channel();
+    let command = match cli::parse(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if command == Command::Help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    let config = match utils::config::get_configs() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("gmtui: {}", e);
+            process::exit(1);
+        }
+    };
+    if command != Command::Tui {
+        if let Err(e) = cli::run(command, &config) {
+            eprintln!("gmtui: {}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Put the terminal back before the panic message is printed, otherwise it's unreadable and
+    // the shell is left in raw mode. Only the main thread's panics end the TUI, the listener's
+    // are noticed by the main loop, which carries on drawing
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") {
+            restore_terminal();
+        }
+        default_hook(info);
+    }));
 
     let stdout = io::stdout();
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     execute!(io::stdout(), EnterAlternateScreen)?;
 
-    let t_width: u16 = (terminal.size().unwrap().width as f64 * 0.98 * 0.75) as u16;
+    let result = run(&mut terminal, config);
+    restore_terminal();
+    if let Err(e) = result {
+        eprintln!("gmtui: {}", e);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn run(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    config: Config,
+) -> Result<(), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel();
+    let (push_tx, push_rx) = mpsc::channel();
 
-    let mut app = App::new(config.secret.to_string(), t_width);
+    let mut app = App::new(config.clone(), terminal.size()?.width)?;
+    app.listener = Some(tx.clone());
     let user_id = app.user_id.clone();
+    let muted = app.conversation_ids(&config.notifications.mute);
+    let notifier = Notifier::new(config.notifications.clone(), &user_id, muted);
 
-    let notify_thread = thread::spawn(move || listener(rx, &user_id, &config.secret));
+    let notify_thread =
+        thread::spawn(move || listener(rx, push_tx, &user_id, &config.secret, notifier));
 
     crossterm::terminal::enable_raw_mode()?;
+    // Show what's cached first, then catch up with the server
+    draw_term(terminal, &mut app)?;
+    app.sync();
     loop {
-        if !poll_input(&mut app, &mut terminal)? {
+        if !poll_input(&mut app, terminal)? {
             break;
         }
-        draw_term(&mut terminal, &mut app);
+        loop {
+            match push_rx.try_recv() {
+                Ok(event) => app.handle_push(event),
+                Err(TryRecvError::Empty) => break,
+                // The listener only leaves without saying it's offline when it panicked
+                Err(TryRecvError::Disconnected) => {
+                    if !matches!(app.connection, ConnectionState::Offline(_)) {
+                        app.connection =
+                            ConnectionState::Offline("live updates stopped".to_string());
+                    }
+                    break;
+                }
+            }
+        }
+        app.focus_listener();
+        draw_term(terminal, &mut app)?;
     }
 
     // Send shutdown to listener
-    tx.send(true).unwrap();
-    notify_thread.join().unwrap();
-
-    // Restore terminal state before exiting
-    crossterm::terminal::disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen)?;
+    // The listener is already gone if it couldn't connect
+    let _ = tx.send(ListenerCommand::Shutdown);
+    let _ = notify_thread.join();
     Ok(())
 }
+
+// Leave raw mode and the alternate screen. Errors are ignored, there's nothing left to do about them
+fn restore_terminal() {
+    let _ = crossterm::terminal::disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
@@ -0,0 +1,177 @@
+use crate::groupme::{Attachment, Member};
+
+use std::cmp::Reverse;
+
+// Most suggestions shown at once while completing a mention
+const MAX_SUGGESTIONS: usize = 5;
+
+/* Find a mention being typed at the end of the text before the cursor, returning the byte
+ * index of its '@' and what has been typed after it
+ * before_cursor: Input up to the cursor
+ */
+pub fn mention_query(before_cursor: &str) -> Option<(usize, &str)> {
+    let start = before_cursor.rfind('@')?;
+    // '@' has to start a word, so email addresses don't trigger completion
+    let starts_word = before_cursor[..start]
+        .chars()
+        .last()
+        .is_none_or(char::is_whitespace);
+    let partial = &before_cursor[start + 1..];
+    if !starts_word || partial.contains('\n') {
+        return None;
+    }
+    Some((start, partial))
+}
+
+/* Nicknames of members starting with what has been typed so far
+ * members: Members of the group being composed in
+ * partial: Text typed after the '@'
+ * user_id: Current user, who isn't suggested
+ */
+pub fn suggestions(members: &[Member], partial: &str, user_id: &str) -> Vec<String> {
+    let partial = partial.to_lowercase();
+    members
+        .iter()
+        .filter(|m| m.user_id != user_id && m.nickname.to_lowercase().starts_with(&partial))
+        .map(|m| m.nickname.clone())
+        .take(MAX_SUGGESTIONS)
+        .collect()
+}
+
+/* Build the mentions attachment for every "@nickname" in a message, or None if nobody is
+ * mentioned
+ * text: Message about to be sent
+ * members: Members of the group the message is sent to
+ */
+pub fn build_mentions(text: &str, members: &[Member]) -> Option<Attachment> {
+    // Longest nicknames first, so "@Sam Smith" isn't taken as a mention of "Sam"
+    let mut by_length: Vec<&Member> = members.iter().collect();
+    by_length.sort_by_key(|m| Reverse(m.nickname.len()));
+
+    let mut taken: Vec<(usize, usize)> = Vec::new();
+    let mut mentions: Vec<(usize, usize, String)> = Vec::new();
+    for member in by_length {
+        let pattern = format!("@{}", member.nickname);
+        for (start, _) in text.match_indices(&pattern) {
+            let end = start + pattern.len();
+            // "@Sam" in "@Samantha" is the start of another name, not a mention of Sam
+            let whole = !text[end..].chars().next().is_some_and(is_name_char);
+            if !whole || taken.iter().any(|&(s, e)| start < e && s < end) {
+                continue;
+            }
+            taken.push((start, end));
+            mentions.push((start, end, member.user_id.clone()));
+        }
+    }
+    if mentions.is_empty() {
+        return None;
+    }
+    mentions.sort();
+
+    // GroupMe counts loci in UTF-16 code units, like the JavaScript clients
+    let utf16_len = |s: &str| s.encode_utf16().count();
+    let loci = mentions
+        .iter()
+        .map(|(start, end, _)| [utf16_len(&text[..*start]), utf16_len(&text[*start..*end])])
+        .collect();
+    let user_ids = mentions.into_iter().map(|(_, _, id)| id).collect();
+    Some(Attachment::Mentions { user_ids, loci })
+}
+
+// Characters that carry on a name, so a mention can't end right before one
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(user_id: &str, nickname: &str) -> Member {
+        Member {
+            id: format!("m{}", user_id),
+            user_id: user_id.to_string(),
+            nickname: nickname.to_string(),
+            image_url: None,
+            muted: false,
+            roles: Vec::new(),
+        }
+    }
+
+    // User ids and loci of the mentions in a message
+    fn mentions(text: &str, members: &[Member]) -> Option<(Vec<String>, Vec<[usize; 2]>)> {
+        match build_mentions(text, members)? {
+            Attachment::Mentions { user_ids, loci } => Some((user_ids, loci)),
+            attachment => panic!("expected mentions, got {:?}", attachment),
+        }
+    }
+
+    #[test]
+    fn mentions_in_text_order() {
+        let members = [member("1", "Sam"), member("2", "Ann")];
+        assert_eq!(
+            mentions("@Ann and @Sam, hi", &members),
+            Some((vec!["2".to_string(), "1".to_string()], vec![[0, 4], [9, 4]]))
+        );
+        assert_eq!(mentions("no one here", &members), None);
+    }
+
+    #[test]
+    fn loci_count_utf16_code_units() {
+        // The pizza is two UTF-16 code units and four bytes, é is one and two
+        let members = [member("1", "Zoé")];
+        assert_eq!(
+            mentions("🍕 @Zoé", &members),
+            Some((vec!["1".to_string()], vec![[3, 4]]))
+        );
+    }
+
+    #[test]
+    fn longest_nickname_wins_an_overlap() {
+        let members = [member("1", "Sam"), member("2", "Sam Smith")];
+        assert_eq!(
+            mentions("@Sam Smith and @Sam", &members),
+            Some((
+                vec!["2".to_string(), "1".to_string()],
+                vec![[0, 10], [15, 4]]
+            ))
+        );
+    }
+
+    #[test]
+    fn name_has_to_end_the_mention() {
+        let members = [member("1", "Sam")];
+        assert_eq!(mentions("@Samantha", &members), None);
+        assert_eq!(mentions("@Sam_", &members), None);
+        assert_eq!(
+            mentions("@Sam's turn", &members),
+            Some((vec!["1".to_string()], vec![[0, 4]]))
+        );
+    }
+
+    #[test]
+    fn query_is_the_mention_being_typed() {
+        assert_eq!(mention_query("hi @Sa"), Some((3, "Sa")));
+        assert_eq!(mention_query("@"), Some((0, "")));
+        assert_eq!(mention_query("hi @Sam\nthere"), None);
+        assert_eq!(mention_query("no mention"), None);
+    }
+
+    #[test]
+    fn query_ignores_email_addresses() {
+        assert_eq!(mention_query("mail sam@example"), None);
+        assert_eq!(mention_query("mail sam@"), None);
+    }
+
+    #[test]
+    fn suggests_other_members_by_prefix() {
+        let members = [
+            member("1", "Sam"),
+            member("2", "samantha"),
+            member("3", "Ann"),
+            member("4", "Sammy"),
+        ];
+        assert_eq!(suggestions(&members, "SAM", "4"), ["Sam", "samantha"]);
+        assert_eq!(suggestions(&members, "", "4").len(), 3);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::groupme::{Attachment, Conversation, Message};
+use crate::utils::config::{NotificationConfig, NotifyMethod};
+
+use std::io::{self, Write};
+
+use chrono::prelude::*;
+
+use notify_rust::Notification;
+
+#[cfg(target_os = "macos")]
+static SOUND: &'static str = "Ping";
+
+#[cfg(all(unix, not(target_os = "macos")))]
+static SOUND: &str = "message-new-instant";
+
+#[cfg(target_os = "windows")]
+static SOUND: &'static str = "Mail";
+
+// Decides which pushed alerts are worth interrupting the user for, and how
+pub struct Notifier {
+    config: NotificationConfig,
+    user_id: String,
+    // Ids of muted conversations, with the names in config.toml already resolved
+    muted: Vec<String>,
+}
+
+impl Notifier {
+    /* Build from the notification settings
+     * config: [notifications] from config.toml
+     * user_id: User's id, to recognise mentions
+     * muted: Ids of the conversations config.mute names
+     */
+    pub fn new(config: NotificationConfig, user_id: &str, muted: Vec<String>) -> Notifier {
+        Notifier {
+            config,
+            user_id: user_id.to_string(),
+            muted,
+        }
+    }
+
+    /* Whether an alert should be shown under the configured rules
+     * conv: Conversation the alert is about, None when it isn't known
+     * msg: New message, None for other alerts such as likes
+     * focused: Conversation open in the TUI
+     */
+    pub fn allows(
+        &self,
+        conv: Option<&Conversation>,
+        msg: Option<&Message>,
+        focused: Option<&Conversation>,
+    ) -> bool {
+        if self.config.method == NotifyMethod::None {
+            return false;
+        }
+        if let Some(conv) = conv {
+            if self.muted.iter().any(|id| id == conv.id()) {
+                return false;
+            }
+            if self.config.suppress_focused && focused == Some(conv) {
+                return false;
+            }
+        }
+        if self.in_quiet_hours(Local::now().time()) {
+            return false;
+        }
+        if !self.config.mentions_only {
+            return true;
+        }
+        // A direct message is meant for the user as much as a mention is
+        let direct = conv.is_some_and(|conv| conv.is_direct());
+        match msg {
+            Some(msg) => direct || self.mentions_user(msg) || self.has_keyword(msg),
+            None => false,
+        }
+    }
+
+    /* Show an alert the configured way, ignoring failures
+     * alert: Text GroupMe sent for the notification
+     */
+    pub fn notify(&self, alert: &str) {
+        match self.config.method {
+            NotifyMethod::Desktop => {
+                // A missing notification daemon shouldn't take the connection down with it
+                let _ = Notification::new()
+                    .summary("GroupMe")
+                    .sound_name(SOUND)
+                    .icon("mail-unread")
+                    .body(alert)
+                    .show();
+            }
+            NotifyMethod::Bell => {
+                let mut stdout = io::stdout();
+                let _ = stdout.write_all(b"\x07");
+                let _ = stdout.flush();
+            }
+            NotifyMethod::None => {}
+        }
+    }
+
+    fn mentions_user(&self, msg: &Message) -> bool {
+        msg.attachments.iter().any(|attachment| match attachment {
+            Attachment::Mentions { user_ids, .. } => user_ids.contains(&self.user_id),
+            _ => false,
+        })
+    }
+
+    fn has_keyword(&self, msg: &Message) -> bool {
+        let text = msg.text.as_deref().unwrap_or("").to_lowercase();
+        self.config
+            .keywords
+            .iter()
+            .any(|keyword| !keyword.is_empty() && text.contains(&keyword.to_lowercase()))
+    }
+
+    // Quiet hours can run past midnight, e.g. 22:00-07:00
+    fn in_quiet_hours(&self, now: NaiveTime) -> bool {
+        match self.config.quiet_hours() {
+            Some((start, end)) if start <= end => start <= now && now < end,
+            Some((start, end)) => now >= start || now < end,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> NotificationConfig {
+        NotificationConfig {
+            method: NotifyMethod::Bell,
+            mute: Vec::new(),
+            mentions_only: false,
+            keywords: Vec::new(),
+            quiet_hours: None,
+            suppress_focused: false,
+        }
+    }
+
+    fn notifier(config: NotificationConfig, muted: &[&str]) -> Notifier {
+        let muted = muted.iter().map(|id| id.to_string()).collect();
+        Notifier::new(config, "me", muted)
+    }
+
+    fn message(text: &str, mentioned: &[&str]) -> Message {
+        let user_ids: Vec<_> = mentioned.iter().map(|id| id.to_string()).collect();
+        let loci = vec![[0, 1]; user_ids.len()];
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "name": "Ann",
+            "text": text,
+            "attachments": [{"type": "mentions", "user_ids": user_ids, "loci": loci}],
+        }))
+        .unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms(hour, minute, 0)
+    }
+
+    fn quiet(hours: &str) -> Notifier {
+        notifier(
+            NotificationConfig {
+                quiet_hours: Some(hours.to_string()),
+                ..config()
+            },
+            &[],
+        )
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let notifier = quiet("09:00-17:00");
+        assert!(!notifier.in_quiet_hours(time(8, 59)));
+        assert!(notifier.in_quiet_hours(time(9, 0)));
+        assert!(notifier.in_quiet_hours(time(16, 59)));
+        assert!(!notifier.in_quiet_hours(time(17, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_past_midnight() {
+        let notifier = quiet("22:00-07:00");
+        assert!(!notifier.in_quiet_hours(time(21, 59)));
+        assert!(notifier.in_quiet_hours(time(22, 0)));
+        assert!(notifier.in_quiet_hours(time(23, 59)));
+        assert!(notifier.in_quiet_hours(time(0, 0)));
+        assert!(notifier.in_quiet_hours(time(6, 59)));
+        assert!(!notifier.in_quiet_hours(time(7, 0)));
+        assert!(!notifier.in_quiet_hours(time(12, 0)));
+    }
+
+    #[test]
+    fn same_start_and_end_is_never_quiet() {
+        let quiet = quiet("08:00-08:00");
+        assert!(!quiet.in_quiet_hours(time(8, 0)));
+        assert!(!quiet.in_quiet_hours(time(20, 0)));
+        assert!(!notifier(config(), &[]).in_quiet_hours(time(3, 0)));
+    }
+
+    #[test]
+    fn mentions_only_lets_mentions_keywords_and_direct_messages_through() {
+        let notifier = notifier(
+            NotificationConfig {
+                mentions_only: true,
+                keywords: vec!["Pizza".to_string()],
+                ..config()
+            },
+            &[],
+        );
+        let group = Conversation::Group("g".to_string());
+        let direct = Conversation::Direct("d".to_string());
+        let allows =
+            |conv: &Conversation, msg: Option<&Message>| notifier.allows(Some(conv), msg, None);
+        assert!(!allows(&group, Some(&message("hello", &[]))));
+        assert!(!allows(&group, Some(&message("hello", &["someone"]))));
+        assert!(allows(&group, Some(&message("hello", &["me"]))));
+        assert!(allows(&group, Some(&message("PIZZA tonight?", &[]))));
+        assert!(allows(&direct, Some(&message("hello", &[]))));
+        // Likes and other alerts without a message aren't mentions
+        assert!(!allows(&group, None));
+    }
+
+    #[test]
+    fn mute_and_focus_win_over_mentions_and_keywords() {
+        let config = NotificationConfig {
+            mentions_only: true,
+            keywords: vec!["pizza".to_string()],
+            suppress_focused: true,
+            ..config()
+        };
+        let notifier = notifier(config, &["g"]);
+        let muted = Conversation::Group("g".to_string());
+        let open = Conversation::Group("h".to_string());
+        let msg = message("pizza", &["me"]);
+        assert!(!notifier.allows(Some(&muted), Some(&msg), None));
+        assert!(!notifier.allows(Some(&open), Some(&msg), Some(&open)));
+        assert!(notifier.allows(Some(&open), Some(&msg), Some(&muted)));
+    }
+
+    #[test]
+    fn method_none_never_notifies() {
+        let notifier = notifier(
+            NotificationConfig {
+                method: NotifyMethod::None,
+                ..config()
+            },
+            &[],
+        );
+        let direct = Conversation::Direct("d".to_string());
+        assert!(!notifier.allows(Some(&direct), Some(&message("hi", &["me"])), None));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::cache::Cache;
+use crate::groupme::{Conversation, Message};
+
+use std::collections::HashMap;
+use std::mem;
+
+use chrono::prelude::*;
+
+// Hits past this are dropped, the query should be narrowed instead
+const MAX_HITS: usize = 200;
+
+/* Parsed search query. Words have to all appear in the text, words in double quotes together as
+ * written, and the remaining filters are written as from:<sender>, after:<YYYY-MM-DD> and
+ * before:<YYYY-MM-DD>
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    pub terms: Vec<String>,
+    pub from: Option<String>,
+    // Unix timestamps, after is inclusive and before exclusive
+    pub after: Option<i64>,
+    pub before: Option<i64>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, String> {
+        let mut query = Query::default();
+        for (word, quoted) in words(input) {
+            if quoted {
+                query.terms.push(word.to_lowercase());
+            } else if let Some(name) = word.strip_prefix("from:") {
+                query.from = Some(name.to_lowercase());
+            } else if let Some(date) = word.strip_prefix("after:") {
+                query.after = Some(parse_date(date)?);
+            } else if let Some(date) = word.strip_prefix("before:") {
+                query.before = Some(parse_date(date)?);
+            } else {
+                query.terms.push(word.to_lowercase());
+            }
+        }
+        if query == Query::default() {
+            return Err("Nothing to search for".to_string());
+        }
+        Ok(query)
+    }
+
+    pub fn matches(&self, msg: &Message) -> bool {
+        if self.after.is_some_and(|after| msg.created_at < after)
+            || self.before.is_some_and(|before| msg.created_at >= before)
+        {
+            return false;
+        }
+        if let Some(from) = &self.from {
+            if !msg.name.to_lowercase().contains(from) {
+                return false;
+            }
+        }
+        let text = msg.text.as_deref().unwrap_or("").to_lowercase();
+        self.terms.iter().all(|term| text.contains(term))
+    }
+}
+
+/* Split a query into words, keeping anything in double quotes together. Words starting with a
+ * quote are searched for as they are, so "from:sam" finds the text from:sam. Filters can still
+ * quote their value, as in from:"sam smith"
+ */
+fn words(input: &str) -> Vec<(String, bool)> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut in_word = false;
+    for c in input.chars() {
+        match c {
+            '"' => {
+                quoted |= !in_word;
+                in_word = true;
+                in_quotes = !in_quotes;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !word.is_empty() {
+                    words.push((mem::take(&mut word), quoted));
+                }
+                quoted = false;
+                in_word = false;
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if !word.is_empty() {
+        words.push((word, quoted));
+    }
+    words
+}
+
+// Midnight local time at the start of the given day
+fn parse_date(date: &str) -> Result<i64, String> {
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("Dates are written as YYYY-MM-DD, not {}", date))?;
+    Local
+        .from_local_datetime(&day.and_hms(0, 0, 0))
+        .earliest()
+        .map(|time| time.timestamp())
+        .ok_or_else(|| format!("{} doesn't exist in this time zone", date))
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub conversation: Conversation,
+    pub conversation_name: String,
+    pub message: Message,
+    // Message sent just before the hit, shown to give it some context
+    pub previous: Option<Message>,
+}
+
+/* Search every cached conversation, newest hits first
+ * cache: Cache to search
+ * query: Parsed query
+ * names: Display names of the conversations, ones missing are shown by id
+ */
+pub fn search(
+    cache: &Cache,
+    query: &Query,
+    names: &HashMap<Conversation, String>,
+) -> Vec<SearchHit> {
+    let mut hits = Vec::new();
+    for conv in cache.conversations() {
+        let msgs = cache.messages(&conv);
+        let name = names
+            .get(&conv)
+            .cloned()
+            .unwrap_or_else(|| conv.id().to_string());
+        for (i, msg) in msgs.iter().enumerate() {
+            if query.matches(msg) {
+                hits.push(SearchHit {
+                    conversation: conv.clone(),
+                    conversation_name: name.clone(),
+                    message: msg.clone(),
+                    previous: i.checked_sub(1).map(|prev| msgs[prev].clone()),
+                });
+            }
+        }
+    }
+    hits.sort_by_key(|hit| std::cmp::Reverse(hit.message.created_at));
+    hits.truncate(MAX_HITS);
+    hits
+}
+
+/* Byte ranges of the query's words in a line of text, ignoring case
+ * line: Text to look through
+ * terms: Lowercased words from Query.terms
+ */
+pub fn highlights(line: &str, terms: &[String]) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < line.len() {
+        let found = terms
+            .iter()
+            .filter_map(|term| match_end(&line[start..], term))
+            .max();
+        match found {
+            Some(end) => {
+                ranges.push((start, start + end));
+                start += end;
+            }
+            None => start += line[start..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    ranges
+}
+
+// End of term if text starts with it, comparing lowercased characters
+fn match_end(text: &str, term: &str) -> Option<usize> {
+    let mut term_chars = term.chars();
+    let mut remaining = term.chars().count();
+    for (i, c) in text.char_indices() {
+        for lower in c.to_lowercase() {
+            if term_chars.next() != Some(lower) {
+                return None;
+            }
+            remaining -= 1;
+        }
+        if remaining == 0 {
+            return Some(i + c.len_utf8());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midnight(year: i32, month: u32, day: u32) -> i64 {
+        Local
+            .from_local_datetime(&NaiveDate::from_ymd(year, month, day).and_hms(0, 0, 0))
+            .earliest()
+            .unwrap()
+            .timestamp()
+    }
+
+    // Parts of a line the ranges cover
+    fn highlighted<'a>(line: &'a str, terms: &[&str]) -> Vec<&'a str> {
+        let terms: Vec<String> = terms.iter().map(|term| term.to_string()).collect();
+        highlights(line, &terms)
+            .into_iter()
+            .map(|(start, end)| &line[start..end])
+            .collect()
+    }
+
+    #[test]
+    fn parses_filters() {
+        let query = Query::parse("Dinner from:Sam after:2021-06-01 before:2021-07-01").unwrap();
+        assert_eq!(
+            query,
+            Query {
+                terms: vec!["dinner".to_string()],
+                from: Some("sam".to_string()),
+                after: Some(midnight(2021, 6, 1)),
+                before: Some(midnight(2021, 7, 1)),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_dates() {
+        assert_eq!(
+            Query::parse("after:2021-13-01").unwrap_err(),
+            "Dates are written as YYYY-MM-DD, not 2021-13-01"
+        );
+        assert!(Query::parse("before:01/06/2021").is_err());
+        assert!(Query::parse("after:").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_queries() {
+        assert!(Query::parse("").is_err());
+        assert!(Query::parse("  \"\" ").is_err());
+    }
+
+    #[test]
+    fn quoted_words_stay_together() {
+        let query = Query::parse("\"Pizza Place\" tonight").unwrap();
+        assert_eq!(query.terms, ["pizza place", "tonight"]);
+    }
+
+    #[test]
+    fn quoted_filters_are_words() {
+        let query = Query::parse("\"from:sam\" from:\"Sam Smith\"").unwrap();
+        assert_eq!(query.terms, ["from:sam"]);
+        assert_eq!(query.from.as_deref(), Some("sam smith"));
+    }
+
+    #[test]
+    fn highlights_ignore_case() {
+        assert_eq!(highlighted("Dinner at the DINER", &["din"]), ["Din", "DIN"]);
+        assert_eq!(highlighted("nothing here", &["dinner"]), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn highlights_non_ascii_text() {
+        assert_eq!(highlighted("Café CAFÉ", &["café"]), ["Café", "CAFÉ"]);
+        assert_eq!(highlighted("日本語のテキスト", &["テキ"]), ["テキ"]);
+        assert_eq!(highlighted("🍕 pizza 🍕", &["pizza"]), ["pizza"]);
+    }
+
+    #[test]
+    fn highlights_prefer_the_longest_term() {
+        assert_eq!(
+            highlighted("pizza place", &["pizza", "pizza place"]),
+            ["pizza place"]
+        );
+    }
+
+    #[test]
+    fn highlight_stops_at_a_partial_match() {
+        // İ lowercases to two characters, so it can't match a plain i
+        assert_eq!(highlighted("İi", &["i"]), ["i"]);
+        assert_eq!(highlighted("caf", &["café"]), Vec::<&str>::new());
+    }
+}
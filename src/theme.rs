@@ -0,0 +1,346 @@
+use std::convert::TryFrom;
+use std::env;
+
+use serde::Deserialize;
+
+use tui::style::{Color, Modifier, Style};
+
+// Styles for every part of the TUI, from a preset with any parts config.toml replaces
+#[derive(Deserialize, Clone)]
+#[serde(from = "ThemeConfig")]
+pub struct Theme {
+    // Border of the focused pane
+    pub accent: Style,
+    // Border of the other panes
+    pub border: Style,
+    // Selected group, direct message or suggestion
+    pub highlight: Style,
+    // Selected message, member or search result
+    pub selected: Style,
+    // Names in the group and direct message lists
+    pub text: Style,
+    // Text being written in the message box
+    pub input: Style,
+    // Sender of each message
+    pub name: Style,
+    // Messages mentioning the user, and search matches
+    pub mention: Style,
+    pub attachment: Style,
+    // Quoted replies, who's typing and other secondary text
+    pub dim: Style,
+    // Conversations with unread messages
+    pub unread: Style,
+    pub info: Style,
+    pub warning: Style,
+    pub error: Style,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Dark,
+    Light,
+    HighContrast,
+    // Bold, italics and reversed text only, for terminals without colour
+    Monochrome,
+}
+
+// [theme] table, a preset and the parts of it to replace
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeConfig {
+    preset: Preset,
+    accent: Option<StyleSpec>,
+    border: Option<StyleSpec>,
+    highlight: Option<StyleSpec>,
+    selected: Option<StyleSpec>,
+    text: Option<StyleSpec>,
+    input: Option<StyleSpec>,
+    name: Option<StyleSpec>,
+    mention: Option<StyleSpec>,
+    attachment: Option<StyleSpec>,
+    dim: Option<StyleSpec>,
+    unread: Option<StyleSpec>,
+    info: Option<StyleSpec>,
+    warning: Option<StyleSpec>,
+    error: Option<StyleSpec>,
+}
+
+// Style written as a colour, "on" a background colour and modifiers, e.g. "white on blue bold"
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+struct StyleSpec(Style);
+
+impl Preset {
+    pub fn theme(self) -> Theme {
+        let fg = |color| Style::default().fg(color);
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        let italic = Style::default().add_modifier(Modifier::ITALIC);
+        let reversed = Style::default().add_modifier(Modifier::REVERSED);
+        match self {
+            Preset::Dark => Theme {
+                accent: fg(Color::Magenta),
+                border: Style::default(),
+                highlight: fg(Color::Magenta).add_modifier(Modifier::BOLD),
+                selected: Style::default()
+                    .bg(Color::Black)
+                    .add_modifier(Modifier::ITALIC),
+                text: fg(Color::White),
+                input: fg(Color::White),
+                name: fg(Color::Blue),
+                mention: fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                attachment: fg(Color::Cyan),
+                dim: fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+                unread: bold,
+                info: fg(Color::Green),
+                warning: fg(Color::Yellow),
+                error: fg(Color::Red).add_modifier(Modifier::BOLD),
+            },
+            Preset::Light => Theme {
+                accent: fg(Color::Blue),
+                border: fg(Color::DarkGray),
+                highlight: fg(Color::Blue).add_modifier(Modifier::BOLD),
+                selected: Style::default()
+                    .bg(Color::Gray)
+                    .add_modifier(Modifier::ITALIC),
+                text: fg(Color::Black),
+                input: fg(Color::Black),
+                name: fg(Color::Blue),
+                mention: fg(Color::Red).add_modifier(Modifier::BOLD),
+                attachment: fg(Color::Magenta),
+                dim: fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+                unread: bold,
+                info: fg(Color::Green),
+                warning: fg(Color::Magenta),
+                error: fg(Color::Red).add_modifier(Modifier::BOLD),
+            },
+            Preset::HighContrast => Theme {
+                accent: fg(Color::LightYellow).add_modifier(Modifier::BOLD),
+                border: fg(Color::White),
+                highlight: Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::LightYellow)
+                    .add_modifier(Modifier::BOLD),
+                selected: reversed.add_modifier(Modifier::BOLD),
+                text: fg(Color::White),
+                input: fg(Color::White),
+                name: fg(Color::LightCyan).add_modifier(Modifier::BOLD),
+                mention: fg(Color::LightYellow).add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                attachment: fg(Color::LightGreen),
+                dim: fg(Color::Gray).add_modifier(Modifier::ITALIC),
+                unread: bold.add_modifier(Modifier::UNDERLINED),
+                info: fg(Color::LightGreen),
+                warning: fg(Color::LightYellow),
+                error: fg(Color::LightRed).add_modifier(Modifier::BOLD),
+            },
+            Preset::Monochrome => Theme {
+                accent: bold,
+                border: Style::default(),
+                highlight: reversed.add_modifier(Modifier::BOLD),
+                selected: reversed,
+                text: Style::default(),
+                input: Style::default(),
+                name: bold,
+                mention: bold.add_modifier(Modifier::UNDERLINED),
+                attachment: italic,
+                dim: italic,
+                unread: bold,
+                info: Style::default(),
+                warning: bold,
+                error: bold.add_modifier(Modifier::UNDERLINED),
+            },
+        }
+    }
+}
+
+// NO_COLOR (https://no-color.org) wins over config.toml, as it's usually set for a reason
+impl From<ThemeConfig> for Theme {
+    fn from(config: ThemeConfig) -> Theme {
+        if no_color() {
+            return Preset::Monochrome.theme();
+        }
+        let preset = config.preset.theme();
+        let or = |spec: Option<StyleSpec>, style| spec.map_or(style, |spec| spec.0);
+        Theme {
+            accent: or(config.accent, preset.accent),
+            border: or(config.border, preset.border),
+            highlight: or(config.highlight, preset.highlight),
+            selected: or(config.selected, preset.selected),
+            text: or(config.text, preset.text),
+            input: or(config.input, preset.input),
+            name: or(config.name, preset.name),
+            mention: or(config.mention, preset.mention),
+            attachment: or(config.attachment, preset.attachment),
+            dim: or(config.dim, preset.dim),
+            unread: or(config.unread, preset.unread),
+            info: or(config.info, preset.info),
+            warning: or(config.warning, preset.warning),
+            error: or(config.error, preset.error),
+        }
+    }
+}
+
+impl TryFrom<String> for StyleSpec {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, String> {
+        let mut style = Style::default();
+        let mut fg = None;
+        let mut words = spec.split_whitespace();
+        while let Some(word) = words.next() {
+            let modifier = match word.to_lowercase().as_ref() {
+                "bold" => Modifier::BOLD,
+                "dim" => Modifier::DIM,
+                "italic" => Modifier::ITALIC,
+                "underlined" | "underline" => Modifier::UNDERLINED,
+                "reversed" | "reverse" => Modifier::REVERSED,
+                "crossed_out" | "strikethrough" => Modifier::CROSSED_OUT,
+                "on" => {
+                    let bg = words
+                        .next()
+                        .ok_or_else(|| format!("no background colour after on in {}", spec))?;
+                    style = style.bg(parse_color(bg)?);
+                    continue;
+                }
+                _ if fg.is_some() => {
+                    return Err(format!(
+                        "{} has more than one colour, use on {} for a background",
+                        spec, word
+                    ))
+                }
+                _ => {
+                    fg = Some(parse_color(word)?);
+                    continue;
+                }
+            };
+            style = style.add_modifier(modifier);
+        }
+        if let Some(fg) = fg {
+            style = style.fg(fg);
+        }
+        Ok(StyleSpec(style))
+    }
+}
+
+// Colour given by name, palette index or #rrggbb
+fn parse_color(name: &str) -> Result<Color, String> {
+    let normalized: String = name
+        .to_lowercase()
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-'))
+        .collect();
+    let color = match normalized.as_ref() {
+        "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        hex if hex.starts_with('#') && hex.len() == 7 && hex.is_ascii() => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+            match (channel(1), channel(3), channel(5)) {
+                (Ok(r), Ok(g), Ok(b)) => Color::Rgb(r, g, b),
+                _ => return Err(format!("{} isn't a valid #rrggbb colour", name)),
+            }
+        }
+        index => match index.parse() {
+            Ok(index) => Color::Indexed(index),
+            Err(_) => return Err(format!("unknown colour {}", name)),
+        },
+    };
+    Ok(color)
+}
+
+// Whether NO_COLOR asks for no colour, which it does when set to anything but an empty string
+fn no_color() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(spec: &str) -> Result<Style, String> {
+        StyleSpec::try_from(spec.to_string()).map(|spec| spec.0)
+    }
+
+    #[test]
+    fn foreground_on_background() {
+        assert_eq!(
+            style("black on yellow"),
+            Ok(Style::default().fg(Color::Black).bg(Color::Yellow))
+        );
+    }
+
+    #[test]
+    fn hex_colour_with_modifier() {
+        assert_eq!(
+            style("#ff8800 bold"),
+            Ok(Style::default()
+                .fg(Color::Rgb(0xff, 0x88, 0x00))
+                .add_modifier(Modifier::BOLD))
+        );
+        assert_eq!(parse_color("#FF8800"), Ok(Color::Rgb(0xff, 0x88, 0x00)));
+        assert!(parse_color("#ff88zz").unwrap_err().contains("#rrggbb"));
+        assert!(parse_color("#ff88").is_err());
+    }
+
+    #[test]
+    fn palette_indices() {
+        assert_eq!(parse_color("0"), Ok(Color::Indexed(0)));
+        assert_eq!(parse_color("208"), Ok(Color::Indexed(208)));
+        assert_eq!(parse_color("256"), Err("unknown colour 256".to_string()));
+        assert_eq!(
+            style("white on 236"),
+            Ok(Style::default().fg(Color::White).bg(Color::Indexed(236)))
+        );
+    }
+
+    #[test]
+    fn names_ignore_case_and_separators() {
+        assert_eq!(parse_color("Light_Blue"), Ok(Color::LightBlue));
+        assert_eq!(parse_color("dark-grey"), Ok(Color::DarkGray));
+        assert_eq!(
+            parse_color("mauve"),
+            Err("unknown colour mauve".to_string())
+        );
+    }
+
+    #[test]
+    fn modifiers_without_colours() {
+        assert_eq!(
+            style("bold underline"),
+            Ok(Style::default()
+                .add_modifier(Modifier::BOLD)
+                .add_modifier(Modifier::UNDERLINED))
+        );
+        assert_eq!(style(""), Ok(Style::default()));
+    }
+
+    #[test]
+    fn two_foregrounds_suggest_on() {
+        assert_eq!(
+            style("white blue"),
+            Err("white blue has more than one colour, use on blue for a background".to_string())
+        );
+    }
+
+    #[test]
+    fn on_needs_a_colour() {
+        assert_eq!(
+            style("white on"),
+            Err("no background colour after on in white on".to_string())
+        );
+    }
+}
@@ -1,77 +1,595 @@
+use crate::cache::Cache;
+use crate::error::{AppError, Result};
+use crate::groupme::GroupMeClient;
+use crate::keys::KeyMap;
+use crate::theme::Theme;
+
 use dirs;
 
+use std::ops::RangeInclusive;
 use std::{env, fs, io, path::PathBuf};
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::NaiveTime;
+
 use serde::{Deserialize, Serialize};
 
-use toml;
+use toml::{self, value::Table, Value};
+
+// Every setting with its default, config.toml is layered on top of it
+const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+// Environment variables starting with this override settings, e.g. GMTUI_LAYOUT__SIDEBAR
+const ENV_PREFIX: &str = "GMTUI_";
 
-#[derive(Serialize, Deserialize)]
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub secret: String,
+    // Root of the GroupMe v3 API, only needed when talking to something other than GroupMe
+    pub api_url: Option<String>,
+    // Image service used for uploading attachments
+    pub image_url: Option<String>,
+    // strftime format of the time shown with each message
+    pub timestamp_format: String,
+    pub layout: LayoutConfig,
+    pub theme: Theme,
+    pub notifications: NotificationConfig,
+    pub cache: CacheConfig,
+    pub keybindings: KeyMap,
+}
+
+// [layout] table, sizes of the panes as percentages. Also saved in layout.json once they're
+// changed from the TUI
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LayoutConfig {
+    pub sidebar: u16,
+    pub messages: u16,
+    // Share of the sidebar's height given to groups
+    pub groups: u16,
+    // Share of the messages pane's height given to the message box
+    pub input: u16,
+    // Always (true) or never (false) hide the sidebar, unset hides it below collapse_below
+    pub hide_sidebar: Option<bool>,
+    // Terminal width, in columns, below which the sidebar is hidden
+    pub collapse_below: u16,
+}
+
+// Smallest and largest percentage each part of the layout can be
+const SIDEBAR_RANGE: RangeInclusive<u16> = 5..=60;
+const MESSAGES_RANGE: RangeInclusive<u16> = 30..=95;
+const GROUPS_RANGE: RangeInclusive<u16> = 10..=90;
+const INPUT_RANGE: RangeInclusive<u16> = 3..=50;
+
+// [notifications] table, deciding which pushed messages pop up a notification
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub method: NotifyMethod,
+    // Groups and direct messages, by name or id, that never notify
+    pub mute: Vec<String>,
+    // Only notify for direct messages, mentions of the user and keywords
+    pub mentions_only: bool,
+    // Words that notify even with mentions_only, matched ignoring case
+    pub keywords: Vec<String>,
+    // No notifications between these times, e.g. "22:00-07:00"
+    pub quiet_hours: Option<String>,
+    // Don't notify for the conversation open in the TUI
+    pub suppress_focused: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyMethod {
+    Desktop,
+    // Ring the terminal bell, for terminals without a notification daemon
+    Bell,
+    None,
+}
+
+// [cache] table
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    pub dir: Option<PathBuf>,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            secret: "".to_string(),
+impl Config {
+    pub fn client(&self) -> GroupMeClient {
+        let client = match &self.api_url {
+            Some(url) => GroupMeClient::with_base_url(&self.secret, url),
+            None => GroupMeClient::new(&self.secret),
+        };
+        match &self.image_url {
+            Some(url) => client.with_image_service(url),
+            None => client,
+        }
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache.dir.clone().unwrap_or_else(Cache::default_dir)
+    }
+
+    // Name of the first setting that doesn't make sense, and what's wrong with it
+    fn validate(&self) -> std::result::Result<(), (String, String)> {
+        if self.secret.trim().is_empty() {
+            return Err((
+                "secret".to_string(),
+                "no access token, get one from https://dev.groupme.com/applications".to_string(),
+            ));
+        }
+        let format_broken =
+            StrftimeItems::new(&self.timestamp_format).any(|item| item == Item::Error);
+        if format_broken {
+            return Err((
+                "timestamp_format".to_string(),
+                format!("{} isn't a valid strftime format", self.timestamp_format),
+            ));
+        }
+        self.layout.validate()?;
+        if let Some((action, key, other, mode)) = self.keybindings.conflict() {
+            return Err((
+                format!("keybindings.{}", action),
+                format!("{} is already bound to {} in {}", key, other, mode),
+            ));
+        }
+        if let Some(hours) = &self.notifications.quiet_hours {
+            parse_quiet_hours(hours).map_err(|e| ("notifications.quiet_hours".to_string(), e))?;
+        }
+        Ok(())
+    }
+}
+
+impl LayoutConfig {
+    /* Left margin, sidebar, messages and right margin, as percentages of the terminal's width.
+     * Messages take the sidebar's place when it's hidden
+     * hidden: Whether the sidebar is hidden
+     */
+    pub fn columns(&self, hidden: bool) -> [u16; 4] {
+        let margin = 100 - self.sidebar - self.messages;
+        if hidden {
+            [
+                margin / 2,
+                0,
+                self.sidebar + self.messages,
+                margin - margin / 2,
+            ]
+        } else {
+            [margin / 2, self.sidebar, self.messages, margin - margin / 2]
+        }
+    }
+
+    /* Whether the sidebar is hidden, either for good or because the terminal is too narrow
+     * width: Width of the terminal
+     */
+    pub fn sidebar_hidden(&self, width: u16) -> bool {
+        self.hide_sidebar.unwrap_or(width < self.collapse_below)
+    }
+
+    /* Width messages are wrapped to, inside the messages pane's borders
+     * width: Width of the terminal
+     * hidden: Whether the sidebar is left out, as App::sidebar_hidden() decides
+     */
+    pub fn message_width(&self, width: u16, hidden: bool) -> u16 {
+        let share = self.columns(hidden)[2];
+        (width as f64 * 0.98 * share as f64 / 100.0) as u16
+    }
+
+    /* Widen or narrow the sidebar, the messages pane making up the difference so the margins
+     * stay the same
+     * step: Percentage points to widen by, negative to narrow
+     */
+    pub fn resize_sidebar(&mut self, step: i16) {
+        let sidebar = step_within(self.sidebar, step, &SIDEBAR_RANGE);
+        let messages = step_within(
+            self.messages,
+            self.sidebar as i16 - sidebar as i16,
+            &MESSAGES_RANGE,
+        );
+        if sidebar + messages <= 100 {
+            self.sidebar = sidebar;
+            self.messages = messages;
+        }
+    }
+
+    // Grow the groups list by step percentage points of the sidebar, shrinking direct messages
+    pub fn resize_groups(&mut self, step: i16) {
+        self.groups = step_within(self.groups, step, &GROUPS_RANGE);
+    }
+
+    pub fn resize_input(&mut self, step: i16) {
+        self.input = step_within(self.input, step, &INPUT_RANGE);
+    }
+
+    /* Show the sidebar if it's hidden and hide it if it isn't, whatever the terminal's width
+     * width: Width of the terminal
+     */
+    pub fn toggle_sidebar(&mut self, width: u16) {
+        self.hide_sidebar = Some(!self.sidebar_hidden(width));
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), (String, String)> {
+        let within = |key: &str, value: u16, range: RangeInclusive<u16>| {
+            if !range.contains(&value) {
+                Err((
+                    key.to_string(),
+                    format!(
+                        "{} has to be from {} to {}",
+                        value,
+                        range.start(),
+                        range.end()
+                    ),
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        within("layout.sidebar", self.sidebar, SIDEBAR_RANGE)?;
+        within("layout.messages", self.messages, MESSAGES_RANGE)?;
+        within("layout.groups", self.groups, GROUPS_RANGE)?;
+        within("layout.input", self.input, INPUT_RANGE)?;
+        if self.sidebar + self.messages > 100 {
+            return Err((
+                "layout.messages".to_string(),
+                format!(
+                    "sidebar and messages add up to {}%, more than the whole width",
+                    self.sidebar + self.messages
+                ),
+            ));
         }
+        Ok(())
+    }
+}
+
+// Move a percentage by step, stopping at the ends of its range
+fn step_within(value: u16, step: i16, range: &RangeInclusive<u16>) -> u16 {
+    (value as i16 + step).clamp(*range.start() as i16, *range.end() as i16) as u16
+}
+
+impl NotificationConfig {
+    /* Start and end of quiet hours, None when they aren't set or can't be parsed. get_configs
+     * rejects ones that can't be parsed
+     */
+    pub fn quiet_hours(&self) -> Option<(NaiveTime, NaiveTime)> {
+        parse_quiet_hours(self.quiet_hours.as_ref()?).ok()
     }
 }
 
+// Parse "HH:MM-HH:MM" into its start and end
+fn parse_quiet_hours(hours: &str) -> std::result::Result<(NaiveTime, NaiveTime), String> {
+    let invalid = || format!("{} isn't of the form HH:MM-HH:MM", hours);
+    let (start, end) = hours.split_once('-').ok_or_else(invalid)?;
+    let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+    Ok((parse(start)?, parse(end)?))
+}
+
 #[cfg(target_os = "linux")]
 fn config_dir() -> Option<PathBuf> {
-    let mut conf_dir = dirs::config_dir().unwrap();
+    let mut conf_dir = dirs::config_dir()?;
     conf_dir.push("groupme-tui");
     Some(conf_dir)
 }
 
 #[cfg(target_os = "macos")]
 fn config_dir() -> Option<PathBuf> {
-    let mut conf_dir = dirs::config_dir().unwrap();
+    let mut conf_dir = dirs::config_dir()?;
     conf_dir.push("Groupme-tui");
     Some(conf_dir)
 }
 
 #[cfg(target_os = "windows")]
 fn config_dir() -> Option<PathBuf> {
-    let mut conf_dir = dirs::config_dir().unwrap();
+    let mut conf_dir = dirs::config_dir()?;
     conf_dir.push("Groupme-tui");
     Some(conf_dir)
 }
 
-// Returns Config Struct Based on config file, creating one when necessary
-pub fn get_configs() -> Option<Config> {
-    let mut conf_dir = match env::var("GMTUI_CONFIG") {
+// Directory holding config.toml and any other local state, created when necessary
+pub fn config_path() -> PathBuf {
+    let conf_dir = match env::var("GMTUI_CONFIG") {
         Ok(dir) => PathBuf::from(dir),
-        Err(_) => config_dir().unwrap(),
+        // Without a config directory for the platform, fall back to the working directory
+        Err(_) => config_dir().unwrap_or_else(|| PathBuf::from(".groupme-tui")),
     };
 
-    // Create config directory if necessary
+    // Create config directory if necessary. Failing shows up once something is written there
     if !conf_dir.is_dir() {
-        fs::create_dir_all(&conf_dir).unwrap();
+        let _ = fs::create_dir_all(&conf_dir);
     }
 
-    // Potentially look into creating default config file for base settings,
-    // Then overwriting the defaults with the custom config, assuming that
-    // more configuration is implemented outside of just the secret
+    conf_dir
+}
+
+/* Returns the Config built from the defaults, config.toml on top of them and then environment
+ * variables, creating config.toml when necessary. Errors name the setting that's wrong
+ */
+pub fn get_configs() -> Result<Config> {
+    let mut conf_dir = config_path();
     conf_dir.push("config.toml");
     let config_file = conf_dir;
+    let invalid = |msg: String| AppError::Config(format!("{}: {}", config_file.display(), msg));
 
-    if !config_file.is_file() {
+    if !config_file.is_file() && env::var(format!("{}SECRET", ENV_PREFIX)).is_err() {
         let mut secret = String::new();
         println!("Enter GroupMe Access Token, which can be obtained here: https://dev.groupme.com/applications");
-        io::stdin()
-            .read_line(&mut secret)
-            .expect("Failed to read Access Token");
-        secret = secret.trim().to_string();
-        let config = Config { secret };
-        fs::write(&config_file, toml::to_string(&config).unwrap()).unwrap();
+        io::stdin().read_line(&mut secret)?;
+        // Only the secret is written, everything else keeps following the defaults
+        let mut contents = Table::new();
+        contents.insert(
+            "secret".to_string(),
+            Value::String(secret.trim().to_string()),
+        );
+        let contents = toml::to_string(&contents).map_err(|e| AppError::Config(e.to_string()))?;
+        fs::write(&config_file, contents)?;
+    }
+
+    let mut merged: Value = toml::from_str(DEFAULT_CONFIG)
+        .map_err(|e| AppError::Config(format!("built-in defaults: {}", e)))?;
+    if config_file.is_file() {
+        let contents = fs::read_to_string(&config_file)?;
+        let user: Value = toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+        merge(&mut merged, user);
+    }
+    from_settings(merged, env::vars()).map_err(invalid)
+}
+
+/* Apply environment variables over the merged settings and check the result, with errors
+ * naming the key that's wrong and the variable that set it, if one did
+ * merged: Defaults with config.toml layered on top
+ * vars: Environment variables
+ */
+fn from_settings<I: Iterator<Item = (String, String)>>(
+    mut merged: Value,
+    vars: I,
+) -> std::result::Result<Config, String> {
+    let overrides = apply_env(&mut merged, vars);
+
+    // Going through text rather than Value::try_into gets errors that name the key
+    let text = toml::to_string(&merged).map_err(|e| e.to_string())?;
+    let config: Config = toml::from_str(&text).map_err(|e| {
+        // Line numbers would point into the merged settings, not any file the user has
+        let msg = e.to_string();
+        let msg = msg
+            .split(" at line ")
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        with_source(msg, &overrides)
+    })?;
+    config
+        .validate()
+        .map_err(|(key, msg)| with_source(format!("{}: {}", key, msg), &overrides))?;
+
+    Ok(config)
+}
+
+// Layer one set of settings over another, tables are merged key by key
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Table(base), Value::Table(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/* Apply GMTUI_<SECTION>__<KEY> environment variables, returning each key that was set and the
+ * variable that set it. Values are read as TOML, falling back to a plain string. Settings that
+ * are strings stay strings, so a token made of digits isn't read as a number
+ * settings: Settings to override
+ * vars: Environment variables
+ */
+fn apply_env<I: Iterator<Item = (String, String)>>(
+    settings: &mut Value,
+    vars: I,
+) -> Vec<(String, String)> {
+    let mut overrides = Vec::new();
+    for (var, raw) in vars {
+        let path = match var.strip_prefix(ENV_PREFIX) {
+            // GMTUI_CONFIG is where config.toml is, not a setting
+            Some(path) if !path.is_empty() && path != "CONFIG" => path.to_lowercase(),
+            _ => continue,
+        };
+        let keys: Vec<&str> = path.split("__").collect();
+        let value = match get(settings, &keys) {
+            Some(Value::String(_)) => Value::String(raw),
+            _ => toml::from_str::<Table>(&format!("value = {}", raw))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .unwrap_or(Value::String(raw)),
+        };
+        if let Some(table) = settings.as_table_mut() {
+            set(table, &keys, value);
+        }
+        overrides.push((keys.join("."), var));
+    }
+    overrides
+}
+
+// Nested setting, if it's set
+fn get<'a>(settings: &'a Value, keys: &[&str]) -> Option<&'a Value> {
+    keys.iter()
+        .try_fold(settings, |value, key| value.as_table()?.get(*key))
+}
+
+// Set a nested setting, replacing anything in the way that isn't a table
+fn set(table: &mut Table, keys: &[&str], value: Value) {
+    match keys {
+        [] => {}
+        [key] => {
+            table.insert(key.to_string(), value);
+        }
+        [key, rest @ ..] => {
+            let entry = table
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            if let Value::Table(inner) = entry {
+                set(inner, rest, value);
+            }
+        }
+    }
+}
+
+// Point out the environment variable behind a bad setting, so it isn't looked for in the file
+fn with_source(msg: String, overrides: &[(String, String)]) -> String {
+    let from: Vec<&str> = overrides
+        .iter()
+        .filter(|(key, _)| {
+            // Unknown keys are reported by their last part only
+            let last = key.rsplit('.').next().unwrap_or_default();
+            msg.starts_with(&format!("{}:", key))
+                || msg.contains(&format!("`{}`", key))
+                || msg.contains(&format!("`{}`", last))
+        })
+        .map(|(_, var)| var.as_ref())
+        .collect();
+    if from.is_empty() {
+        msg
+    } else {
+        format!("{} (set by {})", msg, from.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built-in defaults plus the access token every config.toml needs
+    fn defaults() -> Value {
+        let mut settings = toml::from_str(DEFAULT_CONFIG).unwrap();
+        merge(&mut settings, toml::from_str("secret = \"token\"").unwrap());
+        settings
     }
 
-    let contents = fs::read_to_string(config_file).unwrap();
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn config(pairs: &[(&str, &str)]) -> Config {
+        match from_settings(defaults(), vars(pairs)) {
+            Ok(config) => config,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn error(pairs: &[(&str, &str)]) -> String {
+        match from_settings(defaults(), vars(pairs)) {
+            Ok(_) => panic!("settings should be rejected"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn defaults_with_a_token_are_valid() {
+        let config = config(&[]);
+        assert_eq!(config.layout.sidebar, 15);
+        assert!(config.notifications.method == NotifyMethod::Desktop);
+    }
+
+    #[test]
+    fn blank_secret_from_environment_is_rejected() {
+        let msg = error(&[("GMTUI_SECRET", " ")]);
+        assert!(msg.starts_with("secret: no access token"), "{}", msg);
+        assert!(msg.ends_with("(set by GMTUI_SECRET)"), "{}", msg);
+    }
+
+    #[test]
+    fn out_of_range_setting_names_key_and_variable() {
+        let msg = error(&[("GMTUI_LAYOUT__SIDEBAR", "70")]);
+        assert!(msg.starts_with("layout.sidebar:"), "{}", msg);
+        assert!(msg.ends_with("(set by GMTUI_LAYOUT__SIDEBAR)"), "{}", msg);
+    }
 
-    let config: Config = toml::from_str(&contents).unwrap();
+    #[test]
+    fn file_errors_dont_blame_the_environment() {
+        let mut merged = defaults();
+        merge(
+            &mut merged,
+            toml::from_str("[layout]\nsidebar = 70").unwrap(),
+        );
+        let msg = match from_settings(merged, vars(&[("GMTUI_LAYOUT__INPUT", "10")])) {
+            Ok(_) => panic!("sidebar should be out of range"),
+            Err(e) => e,
+        };
+        assert!(msg.starts_with("layout.sidebar:"), "{}", msg);
+        assert!(!msg.contains("set by"), "{}", msg);
+    }
+
+    #[test]
+    fn unknown_key_names_variable() {
+        let msg = error(&[("GMTUI_LAYOUT__SIDEBARR", "20")]);
+        assert!(msg.contains("`sidebarr`"), "{}", msg);
+        assert!(msg.ends_with("(set by GMTUI_LAYOUT__SIDEBARR)"), "{}", msg);
+    }
+
+    #[test]
+    fn merge_keeps_sibling_keys() {
+        let mut base = defaults();
+        let over =
+            toml::from_str("[layout]\nsidebar = 30\n[notifications]\nkeywords = [\"lunch\"]")
+                .unwrap();
+        merge(&mut base, over);
 
-    Some(config)
+        let layout = &base["layout"];
+        assert_eq!(layout["sidebar"].as_integer(), Some(30));
+        assert!(layout.get("messages").is_some());
+        assert!(layout.get("collapse_below").is_some());
+        assert_eq!(base["notifications"]["method"].as_str(), Some("desktop"));
+        assert!(base.get("keybindings").is_some());
+    }
+
+    #[test]
+    fn values_are_read_as_toml() {
+        let config = config(&[
+            ("GMTUI_LAYOUT__HIDE_SIDEBAR", "true"),
+            ("GMTUI_NOTIFICATIONS__KEYWORDS", "[\"lunch\", \"deploy\"]"),
+        ]);
+        assert_eq!(config.layout.hide_sidebar, Some(true));
+        assert_eq!(config.notifications.keywords, vec!["lunch", "deploy"]);
+    }
+
+    #[test]
+    fn values_that_arent_toml_are_strings() {
+        let config = config(&[
+            ("GMTUI_NOTIFICATIONS__METHOD", "bell"),
+            ("GMTUI_NOTIFICATIONS__QUIET_HOURS", "22:00-07:00"),
+        ]);
+        assert!(config.notifications.method == NotifyMethod::Bell);
+        assert_eq!(
+            config.notifications.quiet_hours.as_deref(),
+            Some("22:00-07:00")
+        );
+    }
+
+    #[test]
+    fn numeric_secret_stays_a_string() {
+        let config = config(&[("GMTUI_SECRET", "0123456789")]);
+        assert_eq!(config.secret, "0123456789");
+    }
+
+    #[test]
+    fn config_variable_isnt_a_setting() {
+        let mut settings = defaults();
+        let overrides = apply_env(
+            &mut settings,
+            vars(&[("GMTUI_CONFIG", "/tmp/gmtui.toml"), ("HOME", "/root")]),
+        );
+        assert!(overrides.is_empty());
+        assert_eq!(settings, defaults());
+    }
 }
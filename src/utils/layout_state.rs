@@ -0,0 +1,35 @@
+use crate::utils::config::{config_path, LayoutConfig};
+
+use std::{fs, io, path::PathBuf};
+
+// Layout as last changed in the TUI, persisted in layout.json next to config.toml
+
+fn state_file() -> PathBuf {
+    let mut path = config_path();
+    path.push("layout.json");
+    path
+}
+
+/* The saved layout, or the one from config.toml when nothing was saved or what was saved no
+ * longer makes sense
+ * config: [layout] from config.toml
+ */
+pub fn load(config: &LayoutConfig) -> LayoutConfig {
+    fs::read_to_string(state_file())
+        .ok()
+        .and_then(|contents| serde_json::from_str::<LayoutConfig>(&contents).ok())
+        .filter(|layout| layout.validate().is_ok())
+        .unwrap_or_else(|| config.clone())
+}
+
+pub fn save(layout: &LayoutConfig) -> io::Result<()> {
+    fs::write(state_file(), serde_json::to_string(layout)?)
+}
+
+// Forget the saved layout, going back to config.toml's
+pub fn clear() -> io::Result<()> {
+    match fs::remove_file(state_file()) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -1,2 +1,4 @@
 pub mod config;
+pub mod layout_state;
 pub mod logo;
+pub mod read_state;
@@ -0,0 +1,68 @@
+use crate::groupme::Conversation;
+use crate::utils::config::config_path;
+
+use std::collections::HashMap;
+use std::{fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// Last read message of every conversation, persisted in read_state.json next to config.toml
+#[derive(Default, Serialize, Deserialize)]
+pub struct ReadState {
+    #[serde(default)]
+    conversations: HashMap<String, ReadMarker>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ReadMarker {
+    pub last_read_id: String,
+    // Message count of the conversation when it was last read, unread = count - read_count
+    pub read_count: u64,
+}
+
+fn state_file() -> PathBuf {
+    let mut path = config_path();
+    path.push("read_state.json");
+    path
+}
+
+impl ReadState {
+    // Load the stored state, starting over if it is missing or unreadable
+    pub fn load() -> ReadState {
+        fs::read_to_string(state_file())
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(state_file(), serde_json::to_string(self)?)
+    }
+
+    /* Number of unread messages in a conversation. Conversations seen for the first time are
+     * treated as read up to their current message count
+     * conv: Conversation to check
+     * lmid: Id of the newest message in the conversation
+     * count: Current number of messages in the conversation
+     */
+    pub fn unread(&mut self, conv: &Conversation, lmid: &str, count: u64) -> u64 {
+        let marker = self
+            .conversations
+            .entry(conv.key())
+            .or_insert_with(|| ReadMarker {
+                last_read_id: lmid.to_string(),
+                read_count: count,
+            });
+        count.saturating_sub(marker.read_count)
+    }
+
+    pub fn mark_read(&mut self, conv: &Conversation, lmid: &str, count: u64) {
+        self.conversations.insert(
+            conv.key(),
+            ReadMarker {
+                last_read_id: lmid.to_string(),
+                read_count: count,
+            },
+        );
+    }
+}